@@ -7,10 +7,12 @@
 
 #![no_std]
 #![no_main]
-use core::{array::from_fn, num::Wrapping};
 use daisy_embassy::{
     DaisyBoard,
-    audio::HALF_DMA_BUFFER_LENGTH,
+    audio::{
+        BLOCK_LENGTH, PlanarBlock,
+        params::{ParamReader, Params},
+    },
     hal::{self, bind_interrupts, exti::ExtiInput, gpio::Pull, interrupt, mode::Async},
     led::UserLed,
     new_daisy_board,
@@ -18,13 +20,13 @@ use daisy_embassy::{
 use defmt::{debug, unwrap};
 use defmt_rtt as _;
 use embassy_executor::Spawner;
-use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
 use embassy_time::Timer;
 use faust_ui::{UIRange, UISetAny};
 use panic_probe as _;
 mod dsp;
 
-static SHARED_VOLUME: Signal<CriticalSectionRawMutex, f32> = Signal::new();
+const GAIN: usize = 0;
+static PARAMS: Params<1> = Params::new([1.0]);
 
 bind_interrupts!(pub struct Irqs{
     EXTI3 => hal::exti::InterruptHandler<interrupt::typelevel::EXTI3>;
@@ -44,7 +46,6 @@ async fn blink(mut led: UserLed<'static>) {
 
 #[embassy_executor::task]
 async fn handle_gain_button(mut change_gain: ExtiInput<'static, Async>) {
-    SHARED_VOLUME.signal(1.0);
     const GAINS: [f32; 10] = [1.0, 0.8, 0.4, 0.2, 0.1, 0.0, 0.1, 0.2, 0.4, 0.8];
     let mut current_index = 0;
     loop {
@@ -52,7 +53,7 @@ async fn handle_gain_button(mut change_gain: ExtiInput<'static, Async>) {
         current_index = (current_index + 1) % 10;
         let value = GAINS[current_index];
         defmt::info!("gain button pressed. value: {}", value);
-        SHARED_VOLUME.signal(value);
+        PARAMS.set(GAIN, value);
         Timer::after_millis(300).await;
     }
 }
@@ -75,53 +76,38 @@ async fn main(spawner: Spawner) {
         )))
         .unwrap();
 
-    let interface = board
-        .audio_peripherals
-        .prepare_interface(Default::default())
-        .await;
+    let interface = unwrap!(
+        board
+            .audio_peripherals
+            .prepare_interface(Default::default())
+            .await
+    );
 
     dsp::LpVol::class_init(48000);
     let mut dsp = dsp::LpVol::new();
     dsp.instance_init(48000);
+    // the Faust gain is smoothed already, so the parameter jumps once per block
+    let mut params = ParamReader::new(&PARAMS, [0]);
     let mut interface = unwrap!(interface.start_interface().await);
     unwrap!(
         interface
-            .start_callback(|input, output| {
-                process_audio_faust(&mut dsp, input, output);
+            .start_callback_planar(|input, output| {
+                process_audio_faust(&mut dsp, &mut params, input, output);
             })
             .await
     );
 }
 
-fn process_audio_faust(dsp: &mut dsp::LpVol, input: &[u32], output: &mut [u32]) {
-    let ibuf: [[f32; 64]; dsp::FAUST_INPUTS] = from_fn(|_| from_fn(|i| u24_to_f32(input[i])));
-    let mut obuf: [[f32; 64]; dsp::FAUST_OUTPUTS] = from_fn(|_| [0.0_f32; HALF_DMA_BUFFER_LENGTH]);
-
-    // if a new value is recieved, set it.
-    // only checked once per buffer copy
-    if let Some(volume) = SHARED_VOLUME.try_take() {
-        dsp::UIActive::Gain.set(dsp, dsp::UIActive::Gain.map(volume));
-    };
-
-    dsp.compute(HALF_DMA_BUFFER_LENGTH, &ibuf, &mut obuf);
-
-    for (i, f32_value) in obuf[0].iter().enumerate() {
-        output[i] = f32_to_u24(*f32_value);
-    }
-}
-
-// see https://github.com/zlosynth/daisy
-// Convert audio PCM data from u24 to f32,
-#[inline(always)]
-fn u24_to_f32(y: u32) -> f32 {
-    let y = (Wrapping(y) + Wrapping(0x0080_0000)).0 & 0x00FF_FFFF; // convert to i32
-    (y as f32 / 8_388_608.0) - 1.0 // (2^24) / 2
-}
-
-// Convert audio data from f32 to u24 PCM
-#[inline(always)]
-fn f32_to_u24(x: f32) -> u32 {
-    let x = x * 8_388_607.0;
-    let x = x.clamp(-8_388_608.0, 8_388_607.0);
-    (x as i32) as u32
+fn process_audio_faust(
+    dsp: &mut dsp::LpVol,
+    params: &mut ParamReader<'_, 1>,
+    input: &PlanarBlock<f32>,
+    output: &mut PlanarBlock<f32>,
+) {
+    // pick up the latest gain, once per block
+    params.begin_block();
+    let volume = params.advance(GAIN, BLOCK_LENGTH as u32);
+    dsp::UIActive::Gain.set(dsp, dsp::UIActive::Gain.map(volume));
+
+    dsp.compute(BLOCK_LENGTH, input, output);
 }
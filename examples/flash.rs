@@ -2,7 +2,7 @@
 #![no_main]
 
 use daisy_embassy::new_daisy_board;
-use defmt::{error, info};
+use defmt::{error, info, unwrap};
 use embassy_executor::Spawner;
 use embassy_stm32::{bind_interrupts, dma, qspi};
 
@@ -24,7 +24,7 @@ async fn main(_spawner: Spawner) {
     const ADDRESS: u32 = 0x00;
     const SIZE: usize = 8000;
 
-    let mut flash = daisy_p.flash.build_async(p.MDMA_CH0, Irqs);
+    let mut flash = unwrap!(daisy_p.flash.build_async(p.MDMA_CH0, Irqs));
 
     info!("uuid: {}", flash.read_uuid());
     // Create an array of data to write.
@@ -36,12 +36,12 @@ async fn main(_spawner: Spawner) {
 
     // Write it to the flash memory.
     info!("Writting to flash");
-    flash.write_async(ADDRESS, &data).await;
+    unwrap!(flash.write_async(ADDRESS, &data).await);
 
     // Read it back.
     info!("Reading from flash");
     let mut buffer: [u8; SIZE] = [0; SIZE];
-    flash.read_async(ADDRESS, &mut buffer).await;
+    unwrap!(flash.read_async(ADDRESS, &mut buffer).await);
     info!("Read buffer: {:?}", buffer[0..32]);
 
     if data == buffer {
@@ -99,15 +99,22 @@ async fn main(_spawner: Spawner) {
     let p = hal::init(config);
     let mut c = cortex_m::Peripherals::take().unwrap();
     let board = new_daisy_board!(p);
-    let interface = board
-        .audio_peripherals
-        .prepare_interface(Default::default())
-        .await;
+    let interface = unwrap!(
+        board
+            .audio_peripherals
+            .prepare_interface(Default::default())
+            .await
+    );
     let sdram = board.sdram.build(&mut c.MPU, &mut c.SCB);
 
     // Feature flags are needed because of the different pin mappings.
     // The same underlying MCU pin is used in both cases
-    #[cfg(any(feature = "seed", feature = "seed_1_1", feature = "seed_1_2"))]
+    #[cfg(any(
+        feature = "seed",
+        feature = "seed_1_1",
+        feature = "seed_1_2",
+        feature = "auto"
+    ))]
     let pin = board.pins.d16;
 
     #[cfg(feature = "patch_sm")]
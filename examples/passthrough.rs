@@ -35,10 +35,12 @@ async fn main(spawner: Spawner) {
     let led = board.user_led;
     spawner.spawn(blink(led)).unwrap();
 
-    let interface = board
-        .audio_peripherals
-        .prepare_interface(Default::default())
-        .await;
+    let interface = unwrap!(
+        board
+            .audio_peripherals
+            .prepare_interface(Default::default())
+            .await
+    );
 
     let mut interface = unwrap!(interface.start_interface().await);
     unwrap!(
@@ -3,7 +3,7 @@
 
 use core::sync::atomic::{AtomicU8, Ordering};
 
-use daisy_embassy::{audio::HALF_DMA_BUFFER_LENGTH, hal, new_daisy_board};
+use daisy_embassy::{hal, new_daisy_board};
 use defmt::{debug, unwrap};
 use embassy_executor::Spawner;
 use embassy_futures::join::join;
@@ -51,17 +51,24 @@ async fn main(_spawner: Spawner) {
     let config = daisy_embassy::default_rcc();
     let p = hal::init(config);
     let board = new_daisy_board!(p);
-    let interface = board
-        .audio_peripherals
-        .prepare_interface(Default::default())
-        .await;
+    let interface = unwrap!(
+        board
+            .audio_peripherals
+            .prepare_interface(Default::default())
+            .await
+    );
 
     let mute;
     let mut change_freq;
 
     // Feature flags are needed because of the different pin mappings.
     // change_freq maps to the same underlying MCU pin in both cases
-    #[cfg(any(feature = "seed", feature = "seed_1_1", feature = "seed_1_2"))]
+    #[cfg(any(
+        feature = "seed",
+        feature = "seed_1_1",
+        feature = "seed_1_2",
+        feature = "auto"
+    ))]
     {
         mute = Input::new(board.pins.d15, Pull::Up);
         change_freq = ExtiInput::new(board.pins.d16, p.EXTI3, Pull::Up, Irqs);
@@ -88,28 +95,24 @@ async fn main(_spawner: Spawner) {
         }
     };
 
-    let mut buf = [0; HALF_DMA_BUFFER_LENGTH];
     let mut smp_pos: u32 = 0;
 
     let oscillator_fut = async {
         let mut interface = unwrap!(interface.start_interface().await);
         unwrap!(
             interface
-                .start_callback(|_input, output| {
+                .start_callback_f32(|_input, output| {
                     let period = WaveFrequency::from(wave_freq.load(Ordering::SeqCst)).as_period();
-                    for chunk in buf.chunks_mut(2) {
-                        let smp = f32_to_u24(make_triangle_wave(smp_pos % period, period));
+                    for frame in output.iter_mut() {
+                        let smp = make_triangle_wave(smp_pos % period, period);
                         if mute.is_high() {
-                            chunk[0] = smp;
-                            chunk[1] = smp;
+                            *frame = [smp, smp];
                         } else {
                             //if user push mute button, do not send triangle wave
-                            chunk[0] = 0;
-                            chunk[1] = 0;
+                            *frame = [0.0, 0.0];
                         }
                         smp_pos = smp_pos.wrapping_add(1);
                     }
-                    output.copy_from_slice(&buf);
                 })
                 .await
         );
@@ -127,12 +130,3 @@ fn make_triangle_wave(pos: u32, period_smp: u32) -> f32 {
         pos as f32 * (-4.0) / period_smp as f32 + 1.0
     }
 }
-
-/// convert audio data from f32 to u24
-#[inline(always)]
-fn f32_to_u24(x: f32) -> u32 {
-    //return (int16_t) __SSAT((int32_t) (x * 32767.f), 16);
-    let x = x * 8_388_607.0;
-    let x = x.clamp(-8_388_608.0, 8_388_607.0);
-    (x as i32) as u32
-}
@@ -263,10 +263,12 @@ async fn main(spawner: Spawner) {
     let p = embassy_stm32::init(config);
     let board = daisy_embassy::new_daisy_board!(p);
 
-    let interface = board
-        .audio_peripherals
-        .prepare_interface(Default::default())
-        .await;
+    let interface = unwrap!(
+        board
+            .audio_peripherals
+            .prepare_interface(Default::default())
+            .await
+    );
 
     // Configure all required buffers in a static way.
     debug!("USB packet size is {} byte", USB_MAX_PACKET_SIZE);
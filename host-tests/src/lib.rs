@@ -0,0 +1,28 @@
+//! Builds the hardware-independent modules of `daisy-embassy` for the host, to run their unit tests.
+//!
+//! The main crate only builds for the STM32H750 (`.cargo/config.toml` sets the target), so
+//! `cargo test` cannot run its `#[cfg(test)]` modules. This crate includes the same source files
+//! under the same module paths, without embassy-stm32:
+//! ```bash
+//! cd host-tests
+//! cargo test --target x86_64-unknown-linux-gnu
+//! ```
+//! Only modules that do not touch the hardware can be listed here.
+#![no_std]
+// the crate-private helpers are used by the parts that need the hardware, which are not built here
+#![allow(dead_code)]
+
+#[cfg(test)]
+extern crate critical_section;
+
+#[path = "../../src/audio"]
+pub mod audio {
+    mod config;
+    pub mod params;
+    mod processor;
+    pub mod sample;
+    pub mod stream;
+
+    pub use config::*;
+    pub use processor::{AudioProcessor, Chain, PrepareError};
+}
@@ -0,0 +1,94 @@
+//! DMA buffers sized from the block length, see `dma_buffers!`.
+//!
+//! DMA1 cannot reach the DTCM, where the linker puts ordinary statics, so the buffers live in
+//! `.sram1_bss` (D2 SRAM). That section is not initialized at startup: the buffers are zeroed when
+//! they are taken.
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+
+use super::zero_copy::CacheAligned;
+
+/// Transmit and receive DMA rings of one SAI: two blocks of `N` frames of `CH` words each.
+///
+/// `prepare_interface()` brings its own for `BLOCK_LENGTH`. Get them with `dma_buffers!` for
+/// `prepare_interface_with_block_length()` and `Interface::attach_secondary()`. There is no other
+/// way to make them, so they are always where DMA1 can reach them.
+pub struct DmaBuffers<const N: usize, const CH: usize = 2> {
+    tx: CacheAligned<[[[u32; CH]; N]; 2]>,
+    rx: CacheAligned<[[[u32; CH]; N]; 2]>,
+}
+
+impl<const N: usize, const CH: usize> DmaBuffers<N, CH> {
+    /// The transmit and receive rings, as handed to the SAI.
+    pub(crate) fn split(&mut self) -> (&mut [u32], &mut [u32]) {
+        (
+            self.tx.0.as_flattened_mut().as_flattened_mut(),
+            self.rx.0.as_flattened_mut().as_flattened_mut(),
+        )
+    }
+}
+
+/// Uninitialized storage for `DmaBuffers`, used by `dma_buffers!`.
+#[doc(hidden)]
+pub struct DmaBufferCell<const N: usize, const CH: usize = 2>(
+    UnsafeCell<MaybeUninit<DmaBuffers<N, CH>>>,
+);
+
+// SAFETY: the buffers are only reachable through `take()`, which hands out one reference at a time.
+unsafe impl<const N: usize, const CH: usize> Sync for DmaBufferCell<N, CH> {}
+
+impl<const N: usize, const CH: usize> DmaBufferCell<N, CH> {
+    pub const fn uninit() -> Self {
+        Self(UnsafeCell::new(MaybeUninit::uninit()))
+    }
+
+    /// Zeroes the buffers and hands them out.
+    ///
+    /// # Safety
+    /// No reference returned by an earlier call may still be in use.
+    pub unsafe fn take(&'static self) -> &'static mut DmaBuffers<N, CH> {
+        let buffers = self.0.get().cast::<DmaBuffers<N, CH>>();
+        // SAFETY: all-zero is a valid `DmaBuffers`, and the caller guarantees this is the only reference.
+        unsafe {
+            buffers.write_bytes(0, 1);
+            &mut *buffers
+        }
+    }
+}
+
+/// Allocates `DmaBuffers` for blocks of `N` frames (and `CH` words per frame, 2 by default) in
+/// `.sram1_bss`, and returns them as `&'static mut`.
+///
+/// Each invocation owns one set of buffers: evaluating the same invocation a second time panics.
+///
+/// # Example
+/// ```rust
+/// let idle: Interface<Idle, 4> = board
+///     .audio_peripherals
+///     .prepare_interface_with_block_length(Default::default(), daisy_embassy::dma_buffers!(4))
+///     .await
+///     .unwrap();
+///
+/// // a TDM codec with 8 active slots on SAI2
+/// let idle = idle
+///     .attach_secondary(secondary, tdm_config, daisy_embassy::dma_buffers!(4, 8))
+///     .unwrap();
+/// ```
+#[macro_export]
+macro_rules! dma_buffers {
+    ($n:expr) => {
+        $crate::dma_buffers!($n, 2)
+    };
+    ($n:expr, $ch:expr) => {{
+        #[unsafe(link_section = ".sram1_bss")]
+        static BUFFERS: $crate::audio::DmaBufferCell<{ $n }, { $ch }> =
+            $crate::audio::DmaBufferCell::uninit();
+        static TAKEN: ::core::sync::atomic::AtomicBool =
+            ::core::sync::atomic::AtomicBool::new(false);
+        if TAKEN.swap(true, ::core::sync::atomic::Ordering::AcqRel) {
+            ::core::panic!("dma_buffers! evaluated twice");
+        }
+        // SAFETY: `TAKEN` lets this happen only once.
+        unsafe { BUFFERS.take() }
+    }};
+}
@@ -0,0 +1,354 @@
+//! Sample rates, clock ratios and framing of the audio interface, and how they are validated.
+//!
+//! Nothing in here touches the hardware, so it is tested on the host, see `host-tests`.
+use super::sample;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Fs {
+    Fs8000,
+    Fs16000,
+    Fs22050,
+    Fs24000,
+    Fs32000,
+    Fs44100,
+    Fs48000,
+    Fs88200,
+    Fs96000,
+    Fs176400,
+    Fs192000,
+}
+
+impl Fs {
+    pub const fn hz(self) -> u32 {
+        match self {
+            Fs::Fs8000 => 8000,
+            Fs::Fs16000 => 16000,
+            Fs::Fs22050 => 22050,
+            Fs::Fs24000 => 24000,
+            Fs::Fs32000 => 32000,
+            Fs::Fs44100 => 44100,
+            Fs::Fs48000 => 48000,
+            Fs::Fs88200 => 88200,
+            Fs::Fs96000 => 96000,
+            Fs::Fs176400 => 176400,
+            Fs::Fs192000 => 192000,
+        }
+    }
+
+    /// Whether this rate is a multiple of 11.025kHz rather than 8kHz.
+    /// Such rates need a 45.1584MHz-based SAI kernel clock, see `daisy_embassy::rcc_for`.
+    pub const fn is_44k1_family(self) -> bool {
+        matches!(self, Fs::Fs22050 | Fs::Fs44100 | Fs::Fs88200 | Fs::Fs176400)
+    }
+}
+
+/// Ratio between the master clock (MCLK) sent to the codec and the sample rate.
+///
+/// The SAI master clock generator produces 256 x fs, or 512 x fs with its oversampling bit set.
+/// Codecs that need 128 x fs or 384 x fs cannot be clocked from the SAI MCLK output.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum ClockRatio {
+    Ratio256,
+    Ratio512,
+}
+
+impl ClockRatio {
+    pub const fn value(self) -> u32 {
+        match self {
+            ClockRatio::Ratio256 => 256,
+            ClockRatio::Ratio512 => 512,
+        }
+    }
+}
+
+/// Largest number of slots in a TDM frame.
+pub const MAX_TDM_SLOTS: usize = 8;
+
+/// Number of 32-bit slots in a TDM frame.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum TdmSlots {
+    Four,
+    Eight,
+}
+
+impl TdmSlots {
+    pub const fn count(self) -> usize {
+        match self {
+            TdmSlots::Four => 4,
+            TdmSlots::Eight => 8,
+        }
+    }
+}
+
+/// How samples are framed on the SAI data lines.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, defmt::Format)]
+pub enum Framing {
+    /// Two 32-bit slots, 24-bit left-justified, FS high for the left channel.
+    /// This is what the on-board codecs use.
+    #[default]
+    Stereo,
+    /// `slots` 32-bit slots per frame, for multichannel codecs like the CS42448 or AK4619.
+    /// FS is a one bit clock pulse before the first slot, and each slot carries 24-bit data MSB first.
+    ///
+    /// Only the slots set in `active_slots` (bit 0 = slot 0) are transferred, in slot order,
+    /// so a frame in the DMA buffers has `active_slots.count_ones()` words.
+    ///
+    /// Only for SAI2, see `Interface::attach_secondary`. SAI1 rejects it with `ConfigError::TdmOnSai1`.
+    Tdm { slots: TdmSlots, active_slots: u16 },
+}
+
+impl Framing {
+    /// Words per frame in the DMA buffers.
+    pub const fn channels(self) -> usize {
+        match self {
+            Framing::Stereo => sample::CHANNELS,
+            Framing::Tdm { active_slots, .. } => active_slots.count_ones() as usize,
+        }
+    }
+
+    /// Bit clocks per frame.
+    pub const fn frame_length(self) -> u16 {
+        match self {
+            Framing::Stereo => 64,
+            Framing::Tdm { slots, .. } => slots.count() as u16 * 32,
+        }
+    }
+
+    pub(crate) fn validate(self) -> Result<(), ConfigError> {
+        if let Framing::Tdm {
+            slots,
+            active_slots,
+        } = self
+            && (active_slots == 0 || active_slots >> slots.count() != 0)
+        {
+            return Err(ConfigError::InvalidSlotMask);
+        }
+        Ok(())
+    }
+}
+
+/// Sample rates a codec driver accepts, listed per master clock ratio.
+/// Each driver publishes its table as `AudioCodec::CAPABILITIES`.
+pub struct Capabilities {
+    pub modes: &'static [(ClockRatio, &'static [Fs])],
+}
+
+impl Capabilities {
+    pub fn supports(&self, fs: Fs, clock_ratio: ClockRatio) -> bool {
+        self.modes
+            .iter()
+            .any(|(ratio, rates)| *ratio == clock_ratio && rates.contains(&fs))
+    }
+}
+
+/// Sample rate actually produced by the SAI for a requested configuration. See `AudioConfig::accuracy`.
+#[derive(Clone, Copy, Debug, defmt::Format)]
+pub struct SampleRateAccuracy {
+    pub actual_hz: f32,
+    /// Deviation from the requested rate in parts per million. Positive means faster.
+    pub error_ppm: f32,
+}
+
+/// Largest sample rate error `AudioConfig::validate` accepts.
+/// Rates further off than this are almost certainly running from the wrong PLL3 setting.
+pub const MAX_SAMPLE_RATE_ERROR_PPM: f32 = 1000.0;
+
+/// Why an `AudioConfig` was rejected.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum ConfigError {
+    /// The codec cannot run at this sample rate and clock ratio, see `AudioCodec::CAPABILITIES`.
+    UnsupportedByCodec,
+    /// The SAI kernel clock cannot be divided down to this sample rate and clock ratio within
+    /// `MAX_SAMPLE_RATE_ERROR_PPM`. For 44.1kHz-family rates, configure the RCC with `daisy_embassy::rcc_for(fs)`.
+    UnreachableSampleRate,
+    /// SAI2 runs from another kernel clock than SAI1, or the secondary configuration has another
+    /// sample rate or clock ratio. See `Interface::attach_secondary`.
+    SecondaryClockMismatch,
+    /// `Framing::Tdm::active_slots` is empty or names slots beyond `slots`.
+    InvalidSlotMask,
+    /// `Framing::Tdm` was requested for SAI1, which only carries stereo frames.
+    /// Attach TDM codecs to SAI2 with `Interface::attach_secondary`.
+    TdmOnSai1,
+    /// The `DmaBuffers` passed to `Interface::attach_secondary` do not have one word per channel
+    /// of the secondary `Framing`.
+    BufferChannelMismatch,
+}
+
+#[derive(Clone, Copy)]
+pub struct AudioConfig {
+    pub fs: Fs,
+    pub clock_ratio: ClockRatio,
+    pub framing: Framing,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig {
+            fs: Fs::Fs48000,
+            clock_ratio: ClockRatio::Ratio256,
+            framing: Framing::Stereo,
+        }
+    }
+}
+
+impl AudioConfig {
+    /// Master clock divider for the given SAI kernel clock, rounded to the nearest integer.
+    pub const fn mclk_div(&self, kernel_clock: u32) -> u32 {
+        let mclk = self.fs.hz() * self.clock_ratio.value();
+        (kernel_clock + mclk / 2) / mclk
+    }
+
+    /// Reports the sample rate actually produced from `kernel_clock` (in Hz).
+    /// This does not touch the hardware, so it can be evaluated anywhere.
+    pub fn accuracy(&self, kernel_clock: u32) -> SampleRateAccuracy {
+        let mclk_ratio = self.mclk_div(kernel_clock).max(1) * self.clock_ratio.value();
+        let ideal_kernel_clock = mclk_ratio as i64 * self.fs.hz() as i64;
+        let error = kernel_clock as i64 - ideal_kernel_clock;
+        SampleRateAccuracy {
+            actual_hz: kernel_clock as f32 / mclk_ratio as f32,
+            error_ppm: error as f32 * 1_000_000.0 / ideal_kernel_clock as f32,
+        }
+    }
+
+    /// Checks that the codec supports this configuration on SAI1 and that the SAI can produce it
+    /// from `kernel_clock` (in Hz), no further off than `MAX_SAMPLE_RATE_ERROR_PPM`.
+    pub fn validate(
+        &self,
+        capabilities: &Capabilities,
+        kernel_clock: u32,
+    ) -> Result<(), ConfigError> {
+        if !capabilities.supports(self.fs, self.clock_ratio) {
+            return Err(ConfigError::UnsupportedByCodec);
+        }
+        // the SAI1 buffers and callbacks carry stereo frames
+        if self.framing != Framing::Stereo {
+            return Err(ConfigError::TdmOnSai1);
+        }
+        if !(1..=63).contains(&self.mclk_div(kernel_clock))
+            || !(-MAX_SAMPLE_RATE_ERROR_PPM..=MAX_SAMPLE_RATE_ERROR_PPM)
+                .contains(&self.accuracy(kernel_clock).error_ppm)
+        {
+            return Err(ConfigError::UnreachableSampleRate);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KERNEL_CLOCK_48K: u32 = 49_152_000; // `default_rcc()`
+    const KERNEL_CLOCK_44K1: u32 = 45_155_555; // `rcc_for()` with a 44.1kHz-family rate
+
+    const CAPABILITIES: Capabilities = Capabilities {
+        modes: &[
+            (
+                ClockRatio::Ratio256,
+                &[Fs::Fs44100, Fs::Fs48000, Fs::Fs88200, Fs::Fs96000],
+            ),
+            (ClockRatio::Ratio512, &[Fs::Fs48000]),
+        ],
+    };
+
+    fn config(fs: Fs, clock_ratio: ClockRatio) -> AudioConfig {
+        AudioConfig {
+            fs,
+            clock_ratio,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn exact_rates_have_no_error() {
+        for fs in [
+            Fs::Fs8000,
+            Fs::Fs32000,
+            Fs::Fs48000,
+            Fs::Fs96000,
+            Fs::Fs192000,
+        ] {
+            let accuracy = config(fs, ClockRatio::Ratio256).accuracy(KERNEL_CLOCK_48K);
+            assert_eq!(accuracy.actual_hz, fs.hz() as f32);
+            assert_eq!(accuracy.error_ppm, 0.0);
+        }
+    }
+
+    #[test]
+    fn accuracy_of_44k1_family() {
+        let accuracy = config(Fs::Fs44100, ClockRatio::Ratio256).accuracy(KERNEL_CLOCK_44K1);
+        assert!((accuracy.error_ppm + 63.0).abs() < 1.0);
+
+        // divider 4 from the 48kHz-family clock runs at 48kHz
+        let accuracy = config(Fs::Fs44100, ClockRatio::Ratio256).accuracy(KERNEL_CLOCK_48K);
+        assert_eq!(accuracy.actual_hz, 48_000.0);
+        assert!(accuracy.error_ppm > 80_000.0);
+    }
+
+    #[test]
+    fn validate_accepts_reachable_rates() {
+        for fs in [Fs::Fs48000, Fs::Fs96000] {
+            assert_eq!(
+                config(fs, ClockRatio::Ratio256).validate(&CAPABILITIES, KERNEL_CLOCK_48K),
+                Ok(())
+            );
+        }
+        for fs in [Fs::Fs44100, Fs::Fs88200] {
+            assert_eq!(
+                config(fs, ClockRatio::Ratio256).validate(&CAPABILITIES, KERNEL_CLOCK_44K1),
+                Ok(())
+            );
+        }
+        assert_eq!(
+            config(Fs::Fs48000, ClockRatio::Ratio512).validate(&CAPABILITIES, KERNEL_CLOCK_48K),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn validate_rejects_rates_from_the_wrong_clock_family() {
+        assert_eq!(
+            config(Fs::Fs44100, ClockRatio::Ratio256).validate(&CAPABILITIES, KERNEL_CLOCK_48K),
+            Err(ConfigError::UnreachableSampleRate)
+        );
+        assert_eq!(
+            config(Fs::Fs48000, ClockRatio::Ratio256).validate(&CAPABILITIES, KERNEL_CLOCK_44K1),
+            Err(ConfigError::UnreachableSampleRate)
+        );
+    }
+
+    #[test]
+    fn validate_rejects_dividers_out_of_range() {
+        let capabilities = Capabilities {
+            modes: &[(ClockRatio::Ratio512, &[Fs::Fs192000])],
+        };
+        // 512 x 192kHz is faster than the kernel clock
+        assert_eq!(
+            config(Fs::Fs192000, ClockRatio::Ratio512).validate(&capabilities, KERNEL_CLOCK_48K),
+            Err(ConfigError::UnreachableSampleRate)
+        );
+    }
+
+    #[test]
+    fn validate_rejects_tdm_on_sai1() {
+        let audio_config = AudioConfig {
+            framing: Framing::Tdm {
+                slots: TdmSlots::Four,
+                active_slots: 0b1111,
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            audio_config.validate(&CAPABILITIES, KERNEL_CLOCK_48K),
+            Err(ConfigError::TdmOnSai1)
+        );
+    }
+
+    #[test]
+    fn validate_rejects_unsupported_rates() {
+        assert_eq!(
+            config(Fs::Fs8000, ClockRatio::Ratio256).validate(&CAPABILITIES, KERNEL_CLOCK_48K),
+            Err(ConfigError::UnsupportedByCodec)
+        );
+    }
+}
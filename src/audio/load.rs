@@ -0,0 +1,124 @@
+//! CPU load of the audio callback, measured with the DWT cycle counter.
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use cortex_m::peripheral::{DCB, DWT};
+
+/// Weight of a new sample in the running averages, as a power of two (1/16).
+const AVERAGE_SHIFT: u32 = 4;
+
+/// Enables the cycle counter `CpuLoad` relies on. Without it, nothing is measured.
+///
+/// # Example
+/// ```rust
+/// let mut core = cortex_m::Peripherals::take().unwrap();
+/// daisy_embassy::audio::enable_cycle_counter(&mut core.DCB, &mut core.DWT);
+/// ```
+pub fn enable_cycle_counter(dcb: &mut DCB, dwt: &mut DWT) {
+    dcb.enable_trace();
+    dwt.enable_cycle_counter();
+}
+
+/// Cycles spent in the callback compared to the time between two blocks.
+///
+/// Only the audio task writes, and every value is a single atomic,
+/// so another task can read the figures at any time without blocking the audio task.
+/// The block period is measured as well, so the figures follow sample rate changes.
+pub struct CpuLoad {
+    average_cycles: AtomicU32,
+    peak_cycles: AtomicU32,
+    period_cycles: AtomicU32,
+    last_start: AtomicU32,
+    has_last_start: AtomicBool,
+}
+
+impl CpuLoad {
+    pub(crate) const fn new() -> Self {
+        Self {
+            average_cycles: AtomicU32::new(0),
+            peak_cycles: AtomicU32::new(0),
+            period_cycles: AtomicU32::new(0),
+            last_start: AtomicU32::new(0),
+            has_last_start: AtomicBool::new(false),
+        }
+    }
+
+    /// Average cycles per callback.
+    pub fn average_cycles(&self) -> u32 {
+        self.average_cycles.load(Ordering::Relaxed)
+    }
+
+    /// Longest callback since the start or the last `reset_peak()`.
+    pub fn peak_cycles(&self) -> u32 {
+        self.peak_cycles.load(Ordering::Relaxed)
+    }
+
+    /// Average cycles between the start of two callbacks, i.e. the deadline for one block.
+    pub fn block_period_cycles(&self) -> u32 {
+        self.period_cycles.load(Ordering::Relaxed)
+    }
+
+    /// `average_cycles()` as a percentage of the block period.
+    pub fn average_percent(&self) -> f32 {
+        percent(self.average_cycles(), self.block_period_cycles())
+    }
+
+    /// `peak_cycles()` as a percentage of the block period.
+    pub fn peak_percent(&self) -> f32 {
+        percent(self.peak_cycles(), self.block_period_cycles())
+    }
+
+    /// Cycles left before the deadline in the longest callback. Negative when a block was late.
+    pub fn worst_case_slack_cycles(&self) -> i32 {
+        self.block_period_cycles() as i32 - self.peak_cycles() as i32
+    }
+
+    pub fn reset_peak(&self) {
+        self.peak_cycles.store(0, Ordering::Relaxed);
+    }
+
+    /// Forgets the previous block, so the gap while the loop was not running is not measured.
+    pub(crate) fn restart(&self) {
+        self.has_last_start.store(false, Ordering::Relaxed);
+    }
+
+    /// Records one callback that ran from cycle `start` to cycle `end`.
+    pub(crate) fn record(&self, start: u32, end: u32) {
+        let busy = end.wrapping_sub(start);
+        update_average(&self.average_cycles, busy);
+        self.peak_cycles.fetch_max(busy, Ordering::Relaxed);
+
+        if self.has_last_start.swap(true, Ordering::Relaxed) {
+            let period = start.wrapping_sub(self.last_start.load(Ordering::Relaxed));
+            update_average(&self.period_cycles, period);
+        }
+        self.last_start.store(start, Ordering::Relaxed);
+    }
+}
+
+fn update_average(average: &AtomicU32, sample: u32) {
+    let old = average.load(Ordering::Relaxed);
+    let new = if old == 0 {
+        sample
+    } else {
+        (old as i64 + ((sample as i64 - old as i64) >> AVERAGE_SHIFT)) as u32
+    };
+    average.store(new, Ordering::Relaxed);
+}
+
+fn percent(cycles: u32, period: u32) -> f32 {
+    if period == 0 {
+        return 0.0;
+    }
+    cycles as f32 / period as f32 * 100.0
+}
+
+impl defmt::Format for CpuLoad {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "CpuLoad {{ average: {}%, peak: {}%, worst-case slack: {} cycles }}",
+            self.average_percent(),
+            self.peak_percent(),
+            self.worst_case_slack_cycles()
+        )
+    }
+}
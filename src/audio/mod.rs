@@ -0,0 +1,1214 @@
+use core::convert::Infallible;
+use core::marker::PhantomData;
+
+use crate::codec::{AudioCodec, Codec, CodecError, Pins as CodecPins, PowerState, SelfTestError};
+use crate::dcache;
+use cortex_m::peripheral::DWT;
+use defmt::{info, warn};
+use embassy_stm32::{self as hal, Peri, bind_interrupts, dma};
+
+use hal::sai::{self, MasterClockDivider};
+
+pub mod params;
+pub mod sample;
+pub mod stream;
+use sample::{Frame, Sample};
+mod buffers;
+mod config;
+mod load;
+mod processor;
+mod sai_control;
+mod secondary;
+mod stats;
+mod zero_copy;
+pub use buffers::{DmaBufferCell, DmaBuffers};
+pub use config::{
+    AudioConfig, Capabilities, ClockRatio, ConfigError, Framing, Fs, MAX_SAMPLE_RATE_ERROR_PPM,
+    MAX_TDM_SLOTS, SampleRateAccuracy, TdmSlots,
+};
+pub use load::{CpuLoad, enable_cycle_counter};
+pub use processor::{AudioProcessor, Chain, PrepareError};
+use processor::{Converter, RawProcessor};
+use secondary::Secondary;
+pub use secondary::{Sai2Pins, SecondaryAudioIrqs, SecondaryAudioPeripherals};
+pub use stats::{AudioStats, RecoveryPolicy};
+use zero_copy::DmaBufferPtrs;
+
+// - global constants ---------------------------------------------------------
+
+pub const BLOCK_LENGTH: usize = 32; // 32 samples, default block length
+// DMA buffer layout for `BLOCK_LENGTH`; with `prepare_interface_with_block_length::<N>()`, see `DmaBuffers<N>`
+pub const HALF_DMA_BUFFER_LENGTH: usize = BLOCK_LENGTH * 2; //  2 channels
+pub const DMA_BUFFER_LENGTH: usize = HALF_DMA_BUFFER_LENGTH * 2; //  2 half-blocks
+
+pub const MAX_BLOCK_LENGTH: usize = 256; // upper bound for a configurable block length
+const MAX_HALF_DMA_BUFFER_LENGTH: usize = MAX_BLOCK_LENGTH * 2; //  2 channels
+
+// - static data --------------------------------------------------------------
+
+//DMA buffer must be in special region. Refer https://embassy.dev/book/#_stm32_bdma_only_working_out_of_some_ram_regions
+//For `prepare_interface()`; other block lengths and SAI2 bring their own, see `dma_buffers!`.
+#[unsafe(link_section = ".sram1_bss")]
+static DMA_BUFFERS: DmaBufferCell<BLOCK_LENGTH> = DmaBufferCell::uninit();
+
+/// Silence used by the codecs to prefill the transmitter before starting the SAI.
+pub(crate) static SILENCE: [u32; MAX_HALF_DMA_BUFFER_LENGTH] = [0; MAX_HALF_DMA_BUFFER_LENGTH];
+
+static SAI1_STATS: AudioStats = AudioStats::new();
+static SAI1_LOAD: CpuLoad = CpuLoad::new();
+
+/// Words per frame of both codecs at most, see `Interface::last_output`.
+const MAX_CHANNELS: usize = sample::CHANNELS + MAX_TDM_SLOTS;
+
+/// Attempts to realign the DMA rings before `start_callback()` gives up and returns the error.
+const MAX_RECOVERY_ATTEMPTS: usize = 4;
+
+// - Interrupts ---------------------------------------------------------------
+bind_interrupts!(pub struct AudioIrqs{
+    DMA1_STREAM0 => dma::InterruptHandler<embassy_stm32::peripherals::DMA1_CH0>, zero_copy::HalfTransferHandler;
+    DMA1_STREAM1 => dma::InterruptHandler<embassy_stm32::peripherals::DMA1_CH1>, zero_copy::HalfTransferHandler;
+});
+
+// - types --------------------------------------------------------------------
+
+/// One block of `N` interleaved frames in raw SAI words, as in the DMA buffers.
+pub type InterleavedBlock<const N: usize = BLOCK_LENGTH> = [Frame<u32>; N];
+/// Channels per frame with a secondary codec attached, see `Interface::start_callback_4ch()`.
+pub const QUAD_CHANNELS: usize = 4;
+/// One frame of both codecs: `[left 1, right 1, left 2, right 2]`.
+pub type QuadFrame<S> = [S; QUAD_CHANNELS];
+/// One block of `N` samples per channel, `[left, right]`.
+pub type PlanarBlock<S, const N: usize = BLOCK_LENGTH> = [[S; N]; sample::CHANNELS];
+
+/// `AudioPeripherals` is a builder to make `Interface` safely.
+/// It ensures the correct pin mappings and DMA regions for
+/// SAI on every supported Seed revision, preventing invalid peripheral
+/// configurations at compile time.
+/// Use `prepare_interface()` to apply board‐rev-specific SAI setup
+/// and transition into the `Interface<'_, Idle>`. From there you can call `start_interface()` to move to
+/// `Interface<'_, Running>` and begin audio callbacks.
+///
+/// `P` is the codec pin set. It only differs from `CodecPins` inside the `auto` codec,
+/// which hands the pins of the detected codec to its driver.
+pub struct AudioPeripherals<'a, P = CodecPins<'a>> {
+    pub codec_pins: P,
+    pub sai1: Peri<'a, hal::peripherals::SAI1>,
+    pub i2c2: Peri<'a, hal::peripherals::I2C2>,
+    pub dma1_ch0: Peri<'a, hal::peripherals::DMA1_CH0>,
+    pub dma1_ch1: Peri<'a, hal::peripherals::DMA1_CH1>,
+    /// I2C2 TX and RX, for the boards whose codec is configured over I2C.
+    #[cfg(any(feature = "seed_1_1", feature = "patch_sm", feature = "auto"))]
+    pub dma1_ch4: Peri<'a, hal::peripherals::DMA1_CH4>,
+    #[cfg(any(feature = "seed_1_1", feature = "patch_sm", feature = "auto"))]
+    pub dma1_ch5: Peri<'a, hal::peripherals::DMA1_CH5>,
+    /// The I2C2 and DMA1 stream 4 and 5 interrupts, bound by `audio_peripherals!` or by you.
+    #[cfg(any(feature = "seed_1_1", feature = "patch_sm", feature = "auto"))]
+    pub i2c_irqs: crate::codec::CodecI2cIrqs,
+}
+
+impl<'a> AudioPeripherals<'a> {
+    /// Prepares the audio interface.
+    ///
+    /// This method sets up the SAI transmitter and receiver, configures the codec (if necessary),
+    /// allocates DMA buffers, and applies board-specific SAI settings. It returns an `Interface<'a, Idle>`
+    /// in the Idle state, allowing the runtime to decide when to start audio callbacks using `start_interface()`.
+    ///
+    /// # Arguments
+    /// * `audio_config` - Audio configuration parameters such as the sample rate.  
+    ///   You can use `AudioConfig::default()` or `Default::default()` for default settings.
+    ///
+    /// # Errors
+    /// Returns `Error::Config` if the codec cannot run at the requested sample rate and clock ratio
+    /// (see `Codec::CAPABILITIES`), or the SAI kernel clock cannot be divided down to it within
+    /// `MAX_SAMPLE_RATE_ERROR_PPM` (44.1kHz-family rates need `daisy_embassy::rcc_for(fs)`).
+    /// Returns `Error::Codec` if the codec does not respond on I2C, after a few retries.
+    ///
+    /// # Notes
+    /// - This method is async because `seed_1_1` requires I2C communication with the WM8731 codec.
+    /// - The board revision is selected via Cargo features (`seed_1_1`, `seed_1_2`), or detected
+    ///   here with the `auto` feature, see `Interface::revision()`.
+    /// - Callbacks receive blocks of `BLOCK_LENGTH` frames. Use `prepare_interface_with_block_length()`
+    ///   to choose another block length.
+    pub async fn prepare_interface(
+        self,
+        audio_config: AudioConfig,
+    ) -> Result<Interface<'a, Idle>, Error> {
+        // SAFETY: an `Interface` made from an earlier call had to give SAI1 back, so it is gone.
+        let buffers = unsafe { DMA_BUFFERS.take() };
+        self.prepare_interface_with_block_length(audio_config, buffers)
+            .await
+    }
+
+    /// Same as `prepare_interface()`, but callbacks receive blocks of `N` frames.
+    ///
+    /// Smaller blocks lower the latency (the DMA ring holds two blocks), larger blocks
+    /// lower the per-block overhead. `N` must be in `1..=MAX_BLOCK_LENGTH`, which is checked at compile time.
+    /// `buffers` are the DMA rings for this block length, see `dma_buffers!`.
+    ///
+    /// # Example
+    /// ```rust
+    /// let idle: Interface<Idle, 4> = board
+    ///     .audio_peripherals
+    ///     .prepare_interface_with_block_length(Default::default(), daisy_embassy::dma_buffers!(4))
+    ///     .await
+    ///     .unwrap();
+    /// ```
+    pub async fn prepare_interface_with_block_length<const N: usize>(
+        self,
+        audio_config: AudioConfig,
+        buffers: &'a mut DmaBuffers<N>,
+    ) -> Result<Interface<'a, Idle, N>, Error> {
+        self.prepare_interface_with_codec::<Codec<'a>, N>(audio_config, buffers)
+            .await
+    }
+}
+
+impl<'a, P> AudioPeripherals<'a, P> {
+    /// Same as `prepare_interface_with_block_length()`, but with the codec driver `C` instead of
+    /// the one selected by the board feature. `codec_pins` are the pins `C` asks for.
+    ///
+    /// # Example
+    /// ```rust
+    /// let p = AudioPeripherals {
+    ///     codec_pins: MyCodecPins { /* ... */ },
+    ///     sai1: board.audio_peripherals.sai1,
+    ///     i2c2: board.audio_peripherals.i2c2,
+    ///     dma1_ch0: board.audio_peripherals.dma1_ch0,
+    ///     dma1_ch1: board.audio_peripherals.dma1_ch1,
+    ///     // only with `seed_1_1`, `patch_sm` and `auto`
+    ///     dma1_ch4: board.audio_peripherals.dma1_ch4,
+    ///     dma1_ch5: board.audio_peripherals.dma1_ch5,
+    ///     i2c_irqs: board.audio_peripherals.i2c_irqs,
+    /// };
+    /// let idle: Interface<Idle, BLOCK_LENGTH, MyCodec> = p
+    ///     .prepare_interface_with_codec(Default::default(), daisy_embassy::dma_buffers!(BLOCK_LENGTH))
+    ///     .await
+    ///     .unwrap();
+    /// ```
+    pub async fn prepare_interface_with_codec<C: AudioCodec<'a, Pins = P>, const N: usize>(
+        self,
+        audio_config: AudioConfig,
+        buffers: &'a mut DmaBuffers<N>,
+    ) -> Result<Interface<'a, Idle, N, C>, Error> {
+        const {
+            assert!(
+                N > 0 && N <= MAX_BLOCK_LENGTH,
+                "block length must be in 1..=MAX_BLOCK_LENGTH"
+            )
+        };
+        let kernel_clock = hal::rcc::frequency::<hal::peripherals::SAI1>().0;
+        audio_config.validate(&C::CAPABILITIES, kernel_clock)?;
+        log_accuracy(&audio_config);
+
+        let (tx_buffer, rx_buffer) = buffers.split();
+        let dma_buffers = DmaBufferPtrs::new(tx_buffer, rx_buffer);
+        let codec = C::new(self, audio_config, tx_buffer, rx_buffer).await?;
+        sai_control::set_clock_ratio(hal::pac::SAI1, audio_config.clock_ratio);
+
+        Ok(Interface {
+            codec,
+            config: audio_config,
+            last_output: [0; MAX_CHANNELS],
+            fade_in: false,
+            sai_started: false,
+            recovery: RecoveryPolicy::default(),
+            secondary: None,
+            dma_buffers,
+            _state: PhantomData,
+        })
+    }
+}
+
+pub struct Idle {}
+pub struct Running {}
+/// Running, with the callback working directly in the DMA buffers. See `Interface::enter_in_place()`.
+pub struct InPlace {}
+pub struct Suspended {}
+pub trait InterfaceState {}
+impl InterfaceState for Idle {}
+impl InterfaceState for Running {}
+impl InterfaceState for InPlace {}
+impl InterfaceState for Suspended {}
+
+/// decides when and how you start audio callback at runtime.
+/// It enforces a two-state model:
+///
+/// * **Idle** – peripherals configured but SAI not started.
+/// * **Running** – SAI started, ready to execute audio callbacks.
+///
+/// Transition from Idle to Running by calling `start_interface()`, which performs
+/// codec register writes, waits for codec timing, and starts the SAI receiver and transmitter.
+/// Once Running, invoke `start_callback()` to enter a continuous read→process→write loop. Any SAI errors are returned
+/// to the caller for custom handling.
+///
+/// `Interface<'a, S>` manages the setup and runtime of an SAI-based audio stream.
+/// It drives codec initialization (over I2C if required), configures SAI TX/RX,
+/// and enforces a two-state model:
+///
+/// * **Idle** – peripherals configured but SAI not started.
+/// * **Running** – SAI started, ready to execute audio callbacks.
+///
+/// Transition from Idle to Running by calling `start_interface()`, which performs
+/// codec register writes, waits for codec timing, and starts the SAI receiver and transmitter.
+/// Once Running, invoke `start_callback()` to enter a continuous read→process→write loop. Any SAI errors are returned
+/// to the caller for custom handling.
+///
+/// `stop_interface()` goes back from Running to Idle: the output is faded out, the codec output is muted
+/// and the SAI is paused. The Idle interface can be started again with `start_interface()`.
+///
+/// `suspend()` goes from Running to **Suspended** to save power: on top of that, the codec's ADC
+/// and DAC are powered down and MCLK stops. `resume()` brings it back without setting it up again.
+///
+/// `enter_in_place()` goes from Running to **InPlace**, where the callback works directly in the DMA
+/// buffers and the ring buffers are out of use. `exit_in_place()` goes back.
+///
+/// # Example
+/// ```rust
+/// // 1. Configure peripherals into Idle state
+/// let idle: Interface<Idle> = board
+///     .audio_peripherals
+///     .prepare_interface(Default::default())
+///     .await
+///     .unwrap();
+///
+/// // ... initialize your DSP or other resources ...
+///
+/// // 2. Start interface and transition to Running
+/// let mut audio: Interface<Running> = idle
+///     .start_interface()
+///     .await
+///     .unwrap();
+///
+/// // 3. Audio processing loop with error handling
+/// loop {
+///     // Runs until an SAI error occurs, then returns Err(e)
+///     if let Err(e) = audio
+///         .start_callback(|input, output| {
+///             // process `input` samples into `output` buffer
+///         })
+///         .await
+///     {
+///         // handle SAI error e (be quick to avoid overrun),
+///         // or let the interface recover by itself with `set_recovery_policy()`
+///     }
+///
+///     // ... optionally reset or reinitialize DSP ...
+/// }
+///
+/// // 4. Stop to do something glitch-sensitive, then start again
+/// let idle = audio.stop_interface().await;
+/// // ... save to flash ...
+/// let mut audio = idle.start_interface().await.unwrap();
+/// ```
+/// # Notes
+/// - Always call `start_interface()` before `start_callback()`.
+/// - Keep callback and error-handling routines short to prevent SAI overruns.
+/// - `N` is the number of frames per block, see `prepare_interface_with_block_length()`.
+/// - `C` is the codec driver, see `prepare_interface_with_codec()`.
+pub struct Interface<'a, S: InterfaceState, const N: usize = BLOCK_LENGTH, C = Codec<'a>> {
+    codec: C,
+    config: AudioConfig,
+    // last frame handed to SAI1 and SAI2, the starting point of the fade-out in `set_sample_rate()`
+    last_output: [u32; MAX_CHANNELS],
+    // ramp up the next block after the SAI has been restarted
+    fade_in: bool,
+    // the SAI has been started before and is paused by `stop_interface()`
+    sai_started: bool,
+    recovery: RecoveryPolicy,
+    secondary: Option<Secondary<'a>>,
+    // the DMA rings owned by the codec's SAI, for the `InPlace` state
+    dma_buffers: DmaBufferPtrs,
+    _state: PhantomData<S>,
+}
+
+impl<'a, const N: usize, C: AudioCodec<'a>> Interface<'a, Idle, N, C> {
+    /// Adds a second, hardware-configured codec on SAI2 (see `SecondaryAudioPeripherals`), for use with
+    /// `start_callback_4ch()` or `start_callback_multichannel()`. It is started, stopped and reclocked
+    /// together with the first one, and SAI1 and SAI2 are enabled in the same instant, so their
+    /// frames stay aligned.
+    ///
+    /// `audio_config` must have the sample rate and clock ratio of this interface. Its `framing`
+    /// may be `Framing::Tdm` to attach a multichannel codec to the header pins.
+    ///
+    /// # Errors
+    /// Returns `ConfigError::SecondaryClockMismatch` if SAI2 does not run from the same kernel clock
+    /// as SAI1 (`daisy_embassy::default_rcc()` and `rcc_for()` feed both from PLL3), or if the sample
+    /// rate or clock ratio differ. Returns `ConfigError::InvalidSlotMask` for an invalid TDM framing,
+    /// and `ConfigError::BufferChannelMismatch` if `buffers` do not have `framing.channels()` words per frame.
+    pub fn attach_secondary<const CH: usize>(
+        mut self,
+        p: SecondaryAudioPeripherals<'a>,
+        audio_config: AudioConfig,
+        buffers: &'a mut DmaBuffers<N, CH>,
+    ) -> Result<Self, Error> {
+        let kernel_clock = hal::rcc::frequency::<hal::peripherals::SAI1>();
+        if hal::rcc::frequency::<hal::peripherals::SAI2>() != kernel_clock
+            || audio_config.fs != self.config.fs
+            || audio_config.clock_ratio != self.config.clock_ratio
+        {
+            return Err(ConfigError::SecondaryClockMismatch.into());
+        }
+        audio_config.framing.validate()?;
+        if audio_config.framing.channels() != CH {
+            return Err(ConfigError::BufferChannelMismatch.into());
+        }
+        info!("set up secondary SAI2 codec");
+
+        let (tx_buffer, rx_buffer) = buffers.split();
+        self.secondary = Some(Secondary::new(p, audio_config, tx_buffer, rx_buffer));
+        sai_control::set_clock_ratio(hal::pac::SAI2, audio_config.clock_ratio);
+        sai_control::set_frame_length(hal::pac::SAI2, audio_config.framing.frame_length());
+        Ok(self)
+    }
+
+    /// This has to be called before `Interface::start_callback` can be used to ensure proper setup of the interface.
+    /// `Interface::start_callback` should be called immediately afterwards otherwise overruns of the SAI can occur.
+    ///
+    /// After `stop_interface()`, this unmutes the codec output and resumes the paused SAI.
+    /// The first block of the next callback is faded in.
+    pub async fn start_interface(mut self) -> Result<Interface<'a, Running, N, C>, Error> {
+        if self.sai_started {
+            self.codec.resume().await?;
+        } else {
+            self.codec.start().await?;
+            if self.secondary.is_some() {
+                // pause again, to start SAI1 together with SAI2 below
+                sai_control::pause(hal::pac::SAI1);
+            }
+        }
+        if let Some(secondary) = &mut self.secondary {
+            secondary.start().await?;
+        }
+        self.resume_sai();
+        Ok(Interface {
+            codec: self.codec,
+            config: self.config,
+            last_output: self.last_output,
+            fade_in: self.fade_in,
+            sai_started: true,
+            recovery: self.recovery,
+            secondary: self.secondary,
+            dma_buffers: self.dma_buffers,
+            _state: PhantomData,
+        })
+    }
+}
+
+impl<'a, const N: usize, C: AudioCodec<'a>> Interface<'a, Running, N, C> {
+    /// Stops the audio stream and returns to `Idle`.
+    ///
+    /// The output is faded out from the last written frame, the SAI is paused and the codec output
+    /// is muted (WM8731, PCM3060 over I2C) or powered down (AK4556). Call this after dropping the
+    /// `start_callback()` future, like `set_sample_rate()`.
+    ///
+    /// An SAI error during the fade-out or a codec error is logged and the interface is stopped anyway.
+    pub async fn stop_interface(mut self) -> Interface<'a, Idle, N, C> {
+        info!("stop audio interface");
+        if let Err(e) = self.fade_out().await {
+            warn!("fade-out before stop failed: {}", e);
+        }
+        sai_control::pause(hal::pac::SAI1);
+        if let Some(secondary) = &mut self.secondary {
+            secondary.pause();
+        }
+        if let Err(e) = self.codec.stop().await {
+            warn!("codec stop failed: {}", e);
+        }
+
+        Interface {
+            codec: self.codec,
+            config: self.config,
+            last_output: [0; MAX_CHANNELS],
+            fade_in: true,
+            sai_started: true,
+            recovery: self.recovery,
+            secondary: self.secondary,
+            dma_buffers: self.dma_buffers,
+            _state: PhantomData,
+        }
+    }
+
+    /// Stops the audio stream like `stop_interface()`, then powers the codec's ADC and DAC down
+    /// and stops MCLK, for battery-powered devices. Use `resume()` to start again.
+    ///
+    /// Errors are logged and the interface is suspended anyway, as far as the codec allows.
+    pub async fn suspend(mut self) -> Interface<'a, Suspended, N, C> {
+        info!("suspend audio interface");
+        if let Err(e) = self.fade_out().await {
+            warn!("fade-out before suspend failed: {}", e);
+        }
+        sai_control::pause(hal::pac::SAI1);
+        if let Some(secondary) = &mut self.secondary {
+            secondary.pause();
+            secondary.set_master_clock_enabled(false);
+        }
+        if let Err(e) = self.codec.suspend().await {
+            warn!("codec suspend failed: {}", e);
+        }
+        sai_control::set_master_clock_enabled(hal::pac::SAI1, false);
+
+        Interface {
+            codec: self.codec,
+            config: self.config,
+            last_output: [0; MAX_CHANNELS],
+            fade_in: true,
+            sai_started: true,
+            recovery: self.recovery,
+            secondary: self.secondary,
+            dma_buffers: self.dma_buffers,
+            _state: PhantomData,
+        }
+    }
+
+    /// Runs `callback` on every block of `N` interleaved stereo frames in raw SAI words.
+    ///
+    /// A codec attached by `attach_secondary()` plays silence meanwhile; use `start_callback_4ch()`
+    /// or `start_callback_multichannel()` to reach it. This holds for all stereo callbacks and
+    /// `run_processor()`.
+    pub async fn start_callback(
+        &mut self,
+        mut callback: impl FnMut(&[u32], &mut [u32]),
+    ) -> Result<Infallible, sai::Error> {
+        self.run_loop::<{ sample::CHANNELS }>(&mut callback).await
+    }
+
+    /// Drives `processor` instead of a closure: `prepare()` and `reset()` are called first,
+    /// then `process()` for every block, and `on_xrun()` on SAI overruns and underruns.
+    ///
+    /// Returns `Error::Processor` right away if `prepare()` rejects the block length `N`,
+    /// and `Error::Sai` for SAI errors like `start_callback()`.
+    ///
+    /// Pass `&mut processor` to keep it, e.g. to swap processors at runtime:
+    /// ```rust
+    /// let mut current: &mut dyn AudioProcessor = &mut reverb;
+    /// loop {
+    ///     match select(audio.run_processor(&mut current), SWAP.wait()).await {
+    ///         Either::First(Err(e)) => { /* handle SAI or prepare error */ }
+    ///         Either::Second(()) => current = &mut delay,
+    ///     }
+    /// }
+    /// ```
+    pub async fn run_processor<S: Sample>(
+        &mut self,
+        mut processor: impl AudioProcessor<S>,
+    ) -> Result<Infallible, Error> {
+        processor.prepare(self.config.fs, N)?;
+        processor.reset();
+        Ok(self
+            .run_loop::<{ sample::CHANNELS }>(&mut Converter::<_, S, N>::new(processor))
+            .await?)
+    }
+
+    /// The callback loop for `CH` interleaved channels: 2 for SAI1 only, more with the secondary codec.
+    async fn run_loop<const CH: usize>(
+        &mut self,
+        processor: &mut impl RawProcessor,
+    ) -> Result<Infallible, sai::Error> {
+        info!("enter audio callback loop");
+        let mut write_buf = [[0; CH]; N];
+        let mut read_buf = [[0; CH]; N];
+        let measure_load = DWT::cycle_counter_enabled();
+        SAI1_LOAD.restart();
+        loop {
+            if let Err(e) = self.read_frames(&mut read_buf).await {
+                SAI1_STATS.count_overrun();
+                SAI1_LOAD.restart();
+                processor.on_xrun();
+                self.recover(e).await?;
+                continue;
+            }
+            if measure_load {
+                let start = DWT::cycle_count();
+                processor.process_raw(read_buf.as_flattened(), write_buf.as_flattened_mut());
+                SAI1_LOAD.record(start, DWT::cycle_count());
+            } else {
+                processor.process_raw(read_buf.as_flattened(), write_buf.as_flattened_mut());
+            }
+            if self.fade_in {
+                self.fade_in = false;
+                for (i, frame) in write_buf.iter_mut().enumerate() {
+                    for w in frame.iter_mut() {
+                        *w = scale(*w, i + 1, N);
+                    }
+                }
+            }
+            self.last_output = [0; MAX_CHANNELS];
+            self.last_output[..CH].copy_from_slice(&write_buf[N - 1]);
+            if let Err(e) = self.write_frames(&write_buf).await {
+                SAI1_STATS.count_underrun();
+                SAI1_LOAD.restart();
+                processor.on_xrun();
+                self.recover(e).await?;
+            }
+        }
+    }
+
+    /// Reads one block into the first two channels from SAI1 and, for `CH > 2`,
+    /// into the others from SAI2. SAI2 is read even if SAI1 fails, so that both stay in step;
+    /// the first error is returned. For `CH == 2`, SAI2 is serviced by `write_frames`.
+    async fn read_frames<const CH: usize>(
+        &mut self,
+        frames: &mut [[u32; CH]; N],
+    ) -> Result<(), sai::Error> {
+        if CH == sample::CHANNELS {
+            return self.codec.read(frames.as_flattened_mut()).await;
+        }
+        let mut pair = [[0; sample::CHANNELS]; N];
+        let primary = self.codec.read(pair.as_flattened_mut()).await;
+        for (frame, words) in frames.iter_mut().zip(&pair) {
+            frame[..sample::CHANNELS].copy_from_slice(words);
+        }
+        let mut secondary_result = Ok(());
+        if let Some(secondary) = &mut self.secondary {
+            let others = CH - sample::CHANNELS;
+            let mut rest = [[0; CH]; N];
+            let rest = &mut rest.as_flattened_mut()[..N * others];
+            secondary_result = secondary.read(rest).await;
+            for (frame, words) in frames.iter_mut().zip(rest.chunks_exact(others)) {
+                frame[sample::CHANNELS..].copy_from_slice(words);
+            }
+        }
+        primary.and(secondary_result)
+    }
+
+    /// Counterpart of `read_frames`. For `CH == 2`, an attached secondary codec gets a block of
+    /// silence while its input is drained, so its DMA rings keep up with SAI1.
+    async fn write_frames<const CH: usize>(
+        &mut self,
+        frames: &[[u32; CH]; N],
+    ) -> Result<(), sai::Error> {
+        if CH == sample::CHANNELS {
+            let primary = self.codec.write(frames.as_flattened()).await;
+            let secondary_result = match &mut self.secondary {
+                Some(secondary) => secondary.transfer_silence(N).await,
+                None => Ok(()),
+            };
+            return primary.and(secondary_result);
+        }
+        let mut pair = [[0; sample::CHANNELS]; N];
+        for (words, frame) in pair.iter_mut().zip(frames) {
+            words.copy_from_slice(&frame[..sample::CHANNELS]);
+        }
+        let primary = self.codec.write(pair.as_flattened()).await;
+        let mut secondary_result = Ok(());
+        if let Some(secondary) = &mut self.secondary {
+            let others = CH - sample::CHANNELS;
+            let mut rest = [[0; CH]; N];
+            let rest = &mut rest.as_flattened_mut()[..N * others];
+            for (words, frame) in rest.chunks_exact_mut(others).zip(frames) {
+                words.copy_from_slice(&frame[sample::CHANNELS..]);
+            }
+            secondary_result = secondary.write(rest).await;
+        }
+        primary.and(secondary_result)
+    }
+
+    /// Applies the `RecoveryPolicy` after `e` has been counted.
+    ///
+    /// The DMA ring buffers reset their position to the DMA's after an error. Writing two blocks of
+    /// silence while draining the input fills the whole transmit ring, so TX is one ring behind RX
+    /// again, as after `start_interface()`.
+    async fn recover(&mut self, e: sai::Error) -> Result<(), sai::Error> {
+        warn!("SAI error: {}", e);
+        if self.recovery == RecoveryPolicy::ReturnError || !matches!(e, sai::Error::Overrun) {
+            return Err(e);
+        }
+
+        let silence = &SILENCE[..N * sample::CHANNELS];
+        let mut attempts = 0;
+        while let Err(e) = self.realign(silence).await {
+            attempts += 1;
+            if attempts == MAX_RECOVERY_ATTEMPTS {
+                return Err(e);
+            }
+        }
+
+        self.last_output = [0; MAX_CHANNELS];
+        self.fade_in = self.recovery == RecoveryPolicy::RestartWithFadeIn;
+        SAI1_STATS.count_recovery();
+        info!("recovered from SAI error");
+        Ok(())
+    }
+
+    async fn realign(&mut self, silence: &[u32]) -> Result<(), sai::Error> {
+        self.transfer(silence).await?;
+        self.transfer(silence).await
+    }
+
+    /// Changes the sample rate of the running interface.
+    ///
+    /// The output is faded out from the last written frame, the SAI is paused, the master clock
+    /// divider and codec registers are updated, and the SAI resumes. The first block produced by
+    /// the next `start_callback()` is faded in, so the switch does not click.
+    ///
+    /// `start_callback()` never returns on its own, so call this after dropping its future,
+    /// e.g. when a `select` on a rate change signal completes:
+    /// ```rust
+    /// loop {
+    ///     match select(audio.start_callback(&mut process), RATE.wait()).await {
+    ///         Either::First(Err(e)) => { /* handle SAI error */ }
+    ///         Either::Second(fs) => audio.set_sample_rate(fs).await.unwrap(),
+    ///     }
+    /// }
+    /// ```
+    /// The SAI kernel clock and the clock ratio are not changed, so `fs` must be reachable from the
+    /// current kernel clock within `MAX_SAMPLE_RATE_ERROR_PPM`. `daisy_embassy::default_rcc()` only
+    /// serves the 48kHz family and `daisy_embassy::rcc_for(fs)` only the family of `fs`: switching
+    /// between the 48kHz and 44.1kHz families needs the clock tree from `rcc_for(fs)` for the new rate,
+    /// which is applied by `embassy_stm32::init`, not here.
+    ///
+    /// # Errors
+    /// Returns `ConfigError::UnreachableSampleRate` if the current kernel clock cannot reach `fs`, and
+    /// `ConfigError::UnsupportedByCodec` if the codec does not support it. Both are checked before
+    /// anything is touched.
+    /// If the codec cannot be reconfigured, the SAI still switches and `Error::Codec` is returned.
+    pub async fn set_sample_rate(&mut self, fs: Fs) -> Result<(), Error> {
+        info!("switch sample rate to {} Hz", fs.hz());
+        let config = AudioConfig { fs, ..self.config };
+        // rejects a rate of the other family, unless the clock tree was set up for it
+        let kernel_clock = hal::rcc::frequency::<hal::peripherals::SAI1>().0;
+        config.validate(&C::CAPABILITIES, kernel_clock)?;
+        log_accuracy(&config);
+
+        self.fade_out().await?;
+
+        sai_control::pause(hal::pac::SAI1);
+        let divider = config.master_clock_divider();
+        sai_control::set_master_clock_divider(hal::pac::SAI1, divider);
+        if let Some(secondary) = &mut self.secondary {
+            secondary.pause();
+            secondary.set_master_clock_divider(divider);
+        }
+        self.codec.set_master_clock_divider(divider);
+        let codec_result = self.codec.set_sample_rate(fs).await;
+        self.resume_sai();
+
+        self.config = config;
+        self.fade_in = true;
+        Ok(codec_result?)
+    }
+
+    /// Ramps the output of both codecs down from the last written frame and leaves the whole DMA
+    /// rings silent.
+    async fn fade_out(&mut self) -> Result<(), sai::Error> {
+        let mut fade_out = [[0; sample::CHANNELS]; N];
+        for (i, frame) in fade_out.iter_mut().enumerate() {
+            for (w, last) in frame.iter_mut().zip(self.last_output) {
+                *w = scale(last, N - 1 - i, N);
+            }
+        }
+        let primary = self.transfer_primary(fade_out.as_flattened()).await;
+        let secondary_result = match &mut self.secondary {
+            Some(secondary) => {
+                secondary
+                    .transfer_fade_out(&self.last_output[sample::CHANNELS..], N)
+                    .await
+            }
+            None => Ok(()),
+        };
+        primary.and(secondary_result)?;
+        // Two blocks of silence push the fade-out through the DMA ring.
+        let silence = &SILENCE[..N * sample::CHANNELS];
+        self.transfer(silence).await?;
+        self.transfer(silence).await?;
+        self.last_output = [0; MAX_CHANNELS];
+        Ok(())
+    }
+
+    /// Writes one block while draining one block of input, so the receiver does not overrun.
+    /// The secondary codec, if attached, gets a block of silence, so that both stay in step.
+    async fn transfer(&mut self, write_buf: &[u32]) -> Result<(), sai::Error> {
+        let primary = self.transfer_primary(write_buf).await;
+        let secondary_result = match &mut self.secondary {
+            Some(secondary) => secondary.transfer_silence(N).await,
+            None => Ok(()),
+        };
+        primary.and(secondary_result)
+    }
+
+    /// `transfer` for SAI1 alone.
+    async fn transfer_primary(&mut self, write_buf: &[u32]) -> Result<(), sai::Error> {
+        let mut read_buf = [[0; sample::CHANNELS]; N];
+        self.codec.read(read_buf.as_flattened_mut()).await?;
+        self.codec.write(write_buf).await
+    }
+
+    /// Switches to processing directly in the DMA buffers, see `Interface<InPlace>`.
+    /// Call this after dropping the `start_callback()` future, like `set_sample_rate()`.
+    ///
+    /// # Panics
+    /// If a secondary codec is attached: SAI2 would replay its ring buffer, because the in-place
+    /// mode only follows SAI1.
+    pub fn enter_in_place(self) -> Interface<'a, InPlace, N, C> {
+        if self.secondary.is_some() {
+            defmt::panic!("the in-place mode does not support attach_secondary()");
+        }
+        info!("enter in-place mode");
+        Interface {
+            codec: self.codec,
+            config: self.config,
+            last_output: self.last_output,
+            fade_in: self.fade_in,
+            sai_started: true,
+            recovery: self.recovery,
+            secondary: self.secondary,
+            dma_buffers: self.dma_buffers,
+            _state: PhantomData,
+        }
+    }
+
+    /// Same as `start_callback`, but with the codec attached by `attach_secondary()`:
+    /// each frame is `[left 1, right 1, left 2, right 2]` in raw SAI words.
+    ///
+    /// Errors on either SAI are counted in `stats()` and handled according to the `RecoveryPolicy`.
+    ///
+    /// # Panics
+    /// If no secondary codec with stereo framing (or two active TDM slots) is attached.
+    pub async fn start_callback_4ch(
+        &mut self,
+        callback: impl FnMut(&[QuadFrame<u32>], &mut [QuadFrame<u32>]),
+    ) -> Result<Infallible, sai::Error> {
+        self.start_callback_multichannel::<QUAD_CHANNELS>(callback)
+            .await
+    }
+
+    /// Same as `start_callback_4ch`, for a secondary codec with any `Framing`: each frame holds
+    /// `[left, right]` of the on-board codec, followed by the active slots of SAI2 in slot order.
+    /// `CH` is therefore `2 + framing.channels()`.
+    ///
+    /// # Panics
+    /// If no secondary codec is attached, or it does not carry `CH - 2` channels.
+    pub async fn start_callback_multichannel<const CH: usize>(
+        &mut self,
+        mut callback: impl FnMut(&[[u32; CH]], &mut [[u32; CH]]),
+    ) -> Result<Infallible, sai::Error> {
+        const {
+            assert!(
+                CH > sample::CHANNELS && CH <= sample::CHANNELS + MAX_TDM_SLOTS,
+                "CH must be 2 plus the channels of the secondary codec"
+            )
+        };
+        match &self.secondary {
+            Some(secondary) if secondary.channels() == CH - sample::CHANNELS => {}
+            Some(secondary) => defmt::panic!(
+                "callback has {} channels, but the secondary codec carries {}",
+                CH - sample::CHANNELS,
+                secondary.channels()
+            ),
+            None => defmt::panic!("multichannel callbacks need attach_secondary()"),
+        }
+        self.run_loop::<CH>(&mut |input: &[u32], output: &mut [u32]| {
+            callback(input.as_chunks().0, output.as_chunks_mut().0)
+        })
+        .await
+    }
+
+    /// Same as `start_callback`, but the callback works on stereo frames of `S`
+    /// instead of raw SAI words. See [`sample`] for the conversion rules.
+    pub async fn start_callback_typed<S: Sample>(
+        &mut self,
+        mut callback: impl FnMut(&[Frame<S>], &mut [Frame<S>]),
+    ) -> Result<Infallible, sai::Error> {
+        let mut input = [[S::default(); sample::CHANNELS]; N];
+        let mut output = [[S::default(); sample::CHANNELS]; N];
+        self.start_callback(|read_buf, write_buf| {
+            sample::decode(read_buf, &mut input);
+            callback(&input, &mut output);
+            sample::encode(&output, write_buf);
+        })
+        .await
+    }
+
+    /// `start_callback_typed` with normalized `f32` samples in `[-1.0, 1.0)`.
+    pub async fn start_callback_f32(
+        &mut self,
+        callback: impl FnMut(&[Frame<f32>], &mut [Frame<f32>]),
+    ) -> Result<Infallible, sai::Error> {
+        self.start_callback_typed(callback).await
+    }
+
+    /// `start_callback_typed` with full-scale `i32` samples.
+    pub async fn start_callback_i32(
+        &mut self,
+        callback: impl FnMut(&[Frame<i32>], &mut [Frame<i32>]),
+    ) -> Result<Infallible, sai::Error> {
+        self.start_callback_typed(callback).await
+    }
+
+    /// `start_callback_typed` with full-scale `i16` samples.
+    pub async fn start_callback_i16(
+        &mut self,
+        callback: impl FnMut(&[Frame<i16>], &mut [Frame<i16>]),
+    ) -> Result<Infallible, sai::Error> {
+        self.start_callback_typed(callback).await
+    }
+
+    /// Same as `start_callback_f32`, but each channel comes in its own buffer.
+    /// This is the layout expected by most block-based DSP code (e.g. Faust).
+    pub async fn start_callback_planar(
+        &mut self,
+        mut callback: impl FnMut(&PlanarBlock<f32, N>, &mut PlanarBlock<f32, N>),
+    ) -> Result<Infallible, sai::Error> {
+        let mut input: PlanarBlock<f32, N> = [[0.0; N]; sample::CHANNELS];
+        let mut output: PlanarBlock<f32, N> = [[0.0; N]; sample::CHANNELS];
+        self.start_callback(|read_buf, write_buf| {
+            sample::deinterleave(read_buf, &mut input);
+            callback(&input, &mut output);
+            sample::interleave(&output, write_buf);
+        })
+        .await
+    }
+}
+
+impl<'a, const N: usize, C: AudioCodec<'a>> Interface<'a, InPlace, N, C> {
+    /// Same as `start_callback`, but without copying: `input` and `output` are the halves of
+    /// the DMA buffers that the DMA is not using during this block.
+    ///
+    /// The slices only live for one call, so they cannot be kept past the block they belong to.
+    /// D-cache maintenance is done around the callback if the D-cache is enabled.
+    /// The callback has to return before the DMA reaches either half; a late block cannot be undone
+    /// and is counted as an underrun in `stats()`, then handled according to the `RecoveryPolicy`
+    /// (there are no ring buffers to realign, so `Restart` just carries on).
+    ///
+    /// `N` must be a multiple of 4, so each half covers whole cache lines.
+    pub async fn start_callback_in_place(
+        &mut self,
+        mut callback: impl FnMut(&[u32], &mut [u32]),
+    ) -> Result<Infallible, sai::Error> {
+        const {
+            assert!(
+                (N * sample::CHANNELS * size_of::<u32>()) % dcache::CACHE_LINE == 0,
+                "in-place processing needs a block length that is a multiple of 4"
+            )
+        };
+        info!("enter in-place audio callback loop");
+        let half_length = N * sample::CHANNELS;
+        let buffer_length = half_length * 2;
+        let rx_stream = C::RX_DMA_STREAM;
+        let tx_stream = 1 - rx_stream;
+        // SAFETY: the embassy ring buffers are not used in this state (`exit_in_place()` realigns
+        // them first), and the CPU only touches the halves the DMA is not using.
+        let (tx_buffer, rx_buffer) = unsafe { self.dma_buffers.get() };
+
+        let measure_load = DWT::cycle_counter_enabled();
+        SAI1_LOAD.restart();
+        let mut rx_half = zero_copy::dma_half(rx_stream, buffer_length);
+        loop {
+            rx_half = zero_copy::wait_for_half(rx_stream, buffer_length, rx_half).await;
+            // The transmitter runs ahead of the receiver by the prefill and the FIFO,
+            // so its position is read separately.
+            let tx_half = zero_copy::dma_half(tx_stream, buffer_length);
+            let input_start = (1 - rx_half) * half_length;
+            let output_start = (1 - tx_half) * half_length;
+            let input = &mut rx_buffer[input_start..input_start + half_length];
+            let output = &mut tx_buffer[output_start..output_start + half_length];
+
+            dcache::invalidate_dcache(input);
+            if measure_load {
+                let start = DWT::cycle_count();
+                callback(input, output);
+                SAI1_LOAD.record(start, DWT::cycle_count());
+            } else {
+                callback(input, output);
+            }
+            dcache::clean_dcache(output);
+            self.last_output[..sample::CHANNELS]
+                .copy_from_slice(&output[half_length - sample::CHANNELS..]);
+
+            if zero_copy::dma_half(rx_stream, buffer_length) != rx_half
+                || zero_copy::dma_half(tx_stream, buffer_length) != tx_half
+            {
+                SAI1_STATS.count_underrun();
+                SAI1_LOAD.restart();
+                warn!("in-place audio callback missed its deadline");
+                if self.recovery == RecoveryPolicy::ReturnError {
+                    return Err(sai::Error::Overrun);
+                }
+                SAI1_STATS.count_recovery();
+                rx_half = zero_copy::dma_half(rx_stream, buffer_length);
+            }
+        }
+    }
+
+    /// Goes back to the ring buffers, for `start_callback()`, `set_sample_rate()` or `stop_interface()`.
+    /// Call this after dropping the `start_callback_in_place()` future.
+    ///
+    /// The ring buffers fell behind the DMA while the in-place mode ran. They are realigned
+    /// while the output fades out from the last in-place frame, and the first block of the next
+    /// callback is faded in. The expected overruns of the realignment are not counted in `stats()`;
+    /// if it keeps failing, that is logged and left to the next callback.
+    pub async fn exit_in_place(self) -> Interface<'a, Running, N, C> {
+        info!("exit in-place mode");
+        let mut running = Interface {
+            codec: self.codec,
+            config: self.config,
+            last_output: self.last_output,
+            fade_in: true,
+            sai_started: true,
+            recovery: self.recovery,
+            secondary: self.secondary,
+            dma_buffers: self.dma_buffers,
+            _state: PhantomData,
+        };
+        for attempt in 1..=MAX_RECOVERY_ATTEMPTS {
+            match running.fade_out().await {
+                Ok(()) => break,
+                Err(e) if attempt == MAX_RECOVERY_ATTEMPTS => {
+                    warn!("realigning the ring buffers failed: {}", e)
+                }
+                Err(_) => {}
+            }
+        }
+        running
+    }
+}
+
+impl<'a, const N: usize, C: AudioCodec<'a>> Interface<'a, Suspended, N, C> {
+    /// Restarts MCLK, powers the codec up with the settings it had and resumes the SAI.
+    /// The first block of the next callback is faded in.
+    pub async fn resume(mut self) -> Result<Interface<'a, Running, N, C>, Error> {
+        info!("resume audio interface");
+        sai_control::set_master_clock_enabled(hal::pac::SAI1, true);
+        if let Some(secondary) = &mut self.secondary {
+            secondary.set_master_clock_enabled(true);
+        }
+        self.codec.wake().await?;
+        self.resume_sai();
+        Ok(Interface {
+            codec: self.codec,
+            config: self.config,
+            last_output: self.last_output,
+            fade_in: true,
+            sai_started: true,
+            recovery: self.recovery,
+            secondary: self.secondary,
+            dma_buffers: self.dma_buffers,
+            _state: PhantomData,
+        })
+    }
+}
+
+#[cfg(feature = "auto")]
+impl<S: InterfaceState, const N: usize> Interface<'_, S, N> {
+    /// The Seed revision detected by `prepare_interface()`.
+    pub fn revision(&self) -> crate::codec::Revision {
+        self.codec.revision()
+    }
+}
+
+impl<'a, S: InterfaceState, const N: usize, C: AudioCodec<'a>> Interface<'a, S, N, C> {
+    pub fn sample_rate(&self) -> Fs {
+        self.config.fs
+    }
+
+    /// Power state of the codec, see `AudioCodec`.
+    pub fn codec_power_state(&self) -> PowerState {
+        self.codec.power_state()
+    }
+
+    /// The codec driver, for controls beyond `AudioCodec` like the WM8731 gain stages.
+    /// Start and stop it through the interface, not through `AudioCodec` directly.
+    pub fn codec_mut(&mut self) -> &mut C {
+        &mut self.codec
+    }
+
+    /// Logs the codec registers through defmt, see `AudioCodec::log_registers()`.
+    pub async fn log_codec_registers(&mut self) -> Result<(), CodecError> {
+        self.codec.log_registers().await
+    }
+
+    /// Checks that the codec and the SAI agree on the serial format, see `AudioCodec::self_test()`.
+    /// A mismatch is also logged.
+    pub async fn self_test(&mut self) -> Result<(), SelfTestError> {
+        let result = self.codec.self_test().await;
+        match result {
+            Ok(()) => info!("codec self-test passed"),
+            Err(e) => warn!("codec self-test failed: {}", e),
+        }
+        result
+    }
+
+    /// Whether `set_mute()` has any effect with this codec.
+    pub fn can_mute(&self) -> bool {
+        self.codec.can_mute()
+    }
+
+    /// Mutes or unmutes the codec output. Unlike `stop_interface()`, the audio keeps running.
+    pub async fn set_mute(&mut self, mute: bool) -> Result<(), CodecError> {
+        self.codec.set_mute(mute).await
+    }
+
+    pub fn sai_rx_config(&self) -> &sai::Config {
+        self.codec.sai_rx_config()
+    }
+
+    pub fn sai_tx_config(&self) -> &sai::Config {
+        self.codec.sai_tx_config()
+    }
+
+    /// Sets what `start_callback()` does on SAI overruns and underruns.
+    /// The default is `RecoveryPolicy::ReturnError`.
+    pub fn set_recovery_policy(&mut self, policy: RecoveryPolicy) {
+        self.recovery = policy;
+    }
+
+    /// Overrun and underrun counters of this interface. See `AudioStats`.
+    pub fn stats(&self) -> &'static AudioStats {
+        &SAI1_STATS
+    }
+
+    /// Callback timing of this interface. Measured only after `enable_cycle_counter()`.
+    pub fn load(&self) -> &'static CpuLoad {
+        &SAI1_LOAD
+    }
+
+    /// Resumes the paused SAI1 and, if attached, SAI2 in the same instant, so that their
+    /// frames stay aligned. See `sai_control::resume_all`.
+    fn resume_sai(&mut self) {
+        if self.secondary.is_some() {
+            sai_control::resume_all(&[hal::pac::SAI1, hal::pac::SAI2]);
+        } else {
+            sai_control::resume(hal::pac::SAI1);
+        }
+    }
+}
+
+impl Fs {
+    /// Master clock divider for a 256 x fs master clock.
+    pub fn into_clock_divider(self) -> MasterClockDivider {
+        AudioConfig {
+            fs: self,
+            clock_ratio: ClockRatio::Ratio256,
+            ..Default::default()
+        }
+        .master_clock_divider()
+    }
+}
+
+fn log_accuracy(audio_config: &AudioConfig) {
+    let kernel_clock = hal::rcc::frequency::<hal::peripherals::SAI1>().0;
+    let accuracy = audio_config.accuracy(kernel_clock);
+    info!(
+        "requested {} Hz, running at {} Hz ({} ppm)",
+        audio_config.fs.hz(),
+        accuracy.actual_hz,
+        accuracy.error_ppm
+    );
+}
+
+/// Scales a raw SAI word by `num / den`.
+fn scale(raw: u32, num: usize, den: usize) -> u32 {
+    let value = sample::raw_to_i24(raw) as i64 * num as i64 / den as i64;
+    sample::i24_to_raw(value as i32)
+}
+
+/// Errors returned while setting up or reconfiguring an `Interface`.
+#[derive(Debug, defmt::Format)]
+pub enum Error {
+    Config(ConfigError),
+    Sai(sai::Error),
+    Codec(CodecError),
+    Processor(PrepareError),
+}
+
+impl From<ConfigError> for Error {
+    fn from(e: ConfigError) -> Self {
+        Error::Config(e)
+    }
+}
+
+impl From<sai::Error> for Error {
+    fn from(e: sai::Error) -> Self {
+        Error::Sai(e)
+    }
+}
+
+impl From<CodecError> for Error {
+    fn from(e: CodecError) -> Self {
+        Error::Codec(e)
+    }
+}
+
+impl From<PrepareError> for Error {
+    fn from(e: PrepareError) -> Self {
+        Error::Processor(e)
+    }
+}
+
+impl AudioConfig {
+    pub fn master_clock_divider(&self) -> MasterClockDivider {
+        let kernel_clock = hal::rcc::frequency::<hal::peripherals::SAI1>().0;
+        mclk_div_from_u8(self.mclk_div(kernel_clock) as u8)
+    }
+}
+
+//================================================
+
+const fn mclk_div_from_u8(v: u8) -> MasterClockDivider {
+    match v {
+        1 => MasterClockDivider::DIV1,
+        2 => MasterClockDivider::DIV2,
+        3 => MasterClockDivider::DIV3,
+        4 => MasterClockDivider::DIV4,
+        5 => MasterClockDivider::DIV5,
+        6 => MasterClockDivider::DIV6,
+        7 => MasterClockDivider::DIV7,
+        8 => MasterClockDivider::DIV8,
+        9 => MasterClockDivider::DIV9,
+        10 => MasterClockDivider::DIV10,
+        11 => MasterClockDivider::DIV11,
+        12 => MasterClockDivider::DIV12,
+        13 => MasterClockDivider::DIV13,
+        14 => MasterClockDivider::DIV14,
+        15 => MasterClockDivider::DIV15,
+        16 => MasterClockDivider::DIV16,
+        17 => MasterClockDivider::DIV17,
+        18 => MasterClockDivider::DIV18,
+        19 => MasterClockDivider::DIV19,
+        20 => MasterClockDivider::DIV20,
+        21 => MasterClockDivider::DIV21,
+        22 => MasterClockDivider::DIV22,
+        23 => MasterClockDivider::DIV23,
+        24 => MasterClockDivider::DIV24,
+        25 => MasterClockDivider::DIV25,
+        26 => MasterClockDivider::DIV26,
+        27 => MasterClockDivider::DIV27,
+        28 => MasterClockDivider::DIV28,
+        29 => MasterClockDivider::DIV29,
+        30 => MasterClockDivider::DIV30,
+        31 => MasterClockDivider::DIV31,
+        32 => MasterClockDivider::DIV32,
+        33 => MasterClockDivider::DIV33,
+        34 => MasterClockDivider::DIV34,
+        35 => MasterClockDivider::DIV35,
+        36 => MasterClockDivider::DIV36,
+        37 => MasterClockDivider::DIV37,
+        38 => MasterClockDivider::DIV38,
+        39 => MasterClockDivider::DIV39,
+        40 => MasterClockDivider::DIV40,
+        41 => MasterClockDivider::DIV41,
+        42 => MasterClockDivider::DIV42,
+        43 => MasterClockDivider::DIV43,
+        44 => MasterClockDivider::DIV44,
+        45 => MasterClockDivider::DIV45,
+        46 => MasterClockDivider::DIV46,
+        47 => MasterClockDivider::DIV47,
+        48 => MasterClockDivider::DIV48,
+        49 => MasterClockDivider::DIV49,
+        50 => MasterClockDivider::DIV50,
+        51 => MasterClockDivider::DIV51,
+        52 => MasterClockDivider::DIV52,
+        53 => MasterClockDivider::DIV53,
+        54 => MasterClockDivider::DIV54,
+        55 => MasterClockDivider::DIV55,
+        56 => MasterClockDivider::DIV56,
+        57 => MasterClockDivider::DIV57,
+        58 => MasterClockDivider::DIV58,
+        59 => MasterClockDivider::DIV59,
+        60 => MasterClockDivider::DIV60,
+        61 => MasterClockDivider::DIV61,
+        62 => MasterClockDivider::DIV62,
+        63 => MasterClockDivider::DIV63,
+        _ => panic!(),
+    }
+}
@@ -0,0 +1,261 @@
+//! Lock-free `f32` parameters, written by control tasks and read by the audio callback.
+//!
+//! `Params` is shared, usually as a `static`. Any task can `set()` a value at any time, each value
+//! is a single atomic, so neither side ever waits for the other or enters a critical section.
+//! The audio task owns a `ParamReader`, which picks up new values once per block and ramps
+//! towards them, so a jumping knob does not produce zipper noise.
+//!
+//! # Example
+//! ```rust
+//! const GAIN: usize = 0;
+//! const CUTOFF: usize = 1;
+//! static PARAMS: Params<2> = Params::new([1.0, 1000.0]);
+//!
+//! // in a control task
+//! PARAMS.set(GAIN, 0.5);
+//!
+//! // in the audio task: ramp GAIN over 480 samples (10ms at 48kHz), jump CUTOFF
+//! let mut params = ParamReader::new(&PARAMS, [480, 0]);
+//! audio.start_callback_f32(|input, output| {
+//!     params.begin_block();
+//!     let cutoff = params.advance(CUTOFF, BLOCK_LENGTH as u32); // per block
+//!     for (i, o) in input.iter().zip(output) {
+//!         let gain = params.next(GAIN); // per sample
+//!         *o = [i[0] * gain, i[1] * gain];
+//!     }
+//! });
+//! ```
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// `P` parameters, each stored as the bits of an `f32`.
+pub struct Params<const P: usize> {
+    values: [AtomicU32; P],
+}
+
+impl<const P: usize> Params<P> {
+    pub const fn new(initial: [f32; P]) -> Self {
+        let mut values = [const { AtomicU32::new(0) }; P];
+        let mut i = 0;
+        while i < P {
+            values[i] = AtomicU32::new(initial[i].to_bits());
+            i += 1;
+        }
+        Self { values }
+    }
+
+    /// Panics if `index >= P`. `ParamReader` ignores non-finite values and keeps the last finite one.
+    pub fn set(&self, index: usize, value: f32) {
+        self.values[index].store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    /// The latest value written, without smoothing. Panics if `index >= P`.
+    pub fn get(&self, index: usize) -> f32 {
+        f32::from_bits(self.values[index].load(Ordering::Relaxed))
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Ramp {
+    current: f32,
+    target: f32,
+    step: f32,
+    remaining: u32,
+    length: u32,
+}
+
+impl Ramp {
+    /// Restarts the ramp towards `target`. NaN and infinities are ignored: a ramp towards them
+    /// would poison `current` for good.
+    fn retarget(&mut self, target: f32) {
+        if !target.is_finite() || target == self.target {
+            return;
+        }
+        self.target = target;
+        if self.length == 0 {
+            self.current = target;
+            self.remaining = 0;
+        } else {
+            self.step = (target - self.current) / self.length as f32;
+            self.remaining = self.length;
+        }
+    }
+
+    fn advance(&mut self, samples: u32) -> f32 {
+        if samples >= self.remaining {
+            self.current = self.target;
+            self.remaining = 0;
+        } else {
+            self.current += self.step * samples as f32;
+            self.remaining -= samples;
+        }
+        self.current
+    }
+}
+
+/// The audio task's view of `Params`, with a linear ramp per parameter.
+pub struct ParamReader<'a, const P: usize> {
+    params: &'a Params<P>,
+    ramps: [Ramp; P],
+}
+
+impl<'a, const P: usize> ParamReader<'a, P> {
+    /// `ramp_samples[i]` is the length of the ramp to a new value of parameter `i`.
+    /// `0` makes the parameter jump at the next `begin_block()`.
+    /// A parameter whose current value is not finite starts at `0.0`.
+    pub fn new(params: &'a Params<P>, ramp_samples: [u32; P]) -> Self {
+        let mut ramps = [Ramp {
+            current: 0.0,
+            target: 0.0,
+            step: 0.0,
+            remaining: 0,
+            length: 0,
+        }; P];
+        for (i, ramp) in ramps.iter_mut().enumerate() {
+            let value = params.get(i);
+            let value = if value.is_finite() { value } else { 0.0 };
+            ramp.current = value;
+            ramp.target = value;
+            ramp.length = ramp_samples[i];
+        }
+        Self { params, ramps }
+    }
+
+    /// Picks up new values. Call once at the start of every block.
+    /// A new value restarts the ramp from wherever the parameter currently is.
+    pub fn begin_block(&mut self) {
+        for (i, ramp) in self.ramps.iter_mut().enumerate() {
+            ramp.retarget(self.params.get(i));
+        }
+    }
+
+    /// Per-sample smoothing: advances parameter `index` by one sample and returns its value.
+    pub fn next(&mut self, index: usize) -> f32 {
+        self.ramps[index].advance(1)
+    }
+
+    /// Per-block smoothing: advances parameter `index` by `samples` at once and returns its value.
+    pub fn advance(&mut self, index: usize, samples: u32) -> f32 {
+        self.ramps[index].advance(samples)
+    }
+
+    /// The value parameter `index` is ramping towards.
+    pub fn target(&self, index: usize) -> f32 {
+        self.ramps[index].target
+    }
+
+    /// Whether parameter `index` is still ramping.
+    pub fn is_smoothing(&self, index: usize) -> bool {
+        self.ramps[index].remaining > 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn starts_at_the_initial_values() {
+        let params = Params::new([0.25, -3.0]);
+        let mut reader = ParamReader::new(&params, [4, 0]);
+        reader.begin_block();
+        assert_eq!(reader.next(0), 0.25);
+        assert_eq!(reader.next(1), -3.0);
+        assert!(!reader.is_smoothing(0));
+    }
+
+    #[test]
+    fn ramps_over_the_ramp_length_and_lands_on_the_target() {
+        let params = Params::new([0.0]);
+        let mut reader = ParamReader::new(&params, [4]);
+        params.set(0, 1.0);
+        reader.begin_block();
+        assert_eq!(reader.target(0), 1.0);
+        let values = [(); 4].map(|_| reader.next(0));
+        assert_eq!(values, [0.25, 0.5, 0.75, 1.0]);
+        assert!(!reader.is_smoothing(0));
+        assert_eq!(reader.next(0), 1.0);
+    }
+
+    #[test]
+    fn reaches_the_target_exactly_despite_rounding() {
+        let params = Params::new([0.0]);
+        let mut reader = ParamReader::new(&params, [3]);
+        params.set(0, 0.1);
+        reader.begin_block();
+        reader.next(0);
+        reader.next(0);
+        assert!(reader.is_smoothing(0));
+        assert_eq!(reader.next(0), 0.1);
+    }
+
+    #[test]
+    fn advances_a_whole_block_at_once() {
+        let params = Params::new([0.0]);
+        let mut reader = ParamReader::new(&params, [8]);
+        params.set(0, 8.0);
+        reader.begin_block();
+        assert_eq!(reader.advance(0, 2), 2.0);
+        assert_eq!(reader.advance(0, 4), 6.0);
+        // overshooting the ramp stops at the target
+        assert_eq!(reader.advance(0, 16), 8.0);
+        assert!(!reader.is_smoothing(0));
+    }
+
+    #[test]
+    fn zero_length_jumps_at_the_next_block() {
+        let params = Params::new([1.0]);
+        let mut reader = ParamReader::new(&params, [0]);
+        params.set(0, 5.0);
+        assert_eq!(reader.next(0), 1.0);
+        reader.begin_block();
+        assert_eq!(reader.next(0), 5.0);
+        assert!(!reader.is_smoothing(0));
+    }
+
+    #[test]
+    fn retargeting_mid_ramp_restarts_from_the_current_value() {
+        let params = Params::new([0.0]);
+        let mut reader = ParamReader::new(&params, [4]);
+        params.set(0, 4.0);
+        reader.begin_block();
+        assert_eq!(reader.advance(0, 2), 2.0);
+
+        params.set(0, -2.0);
+        reader.begin_block();
+        assert_eq!(reader.target(0), -2.0);
+        // a full ramp length from 2.0, not what was left of the old ramp
+        assert_eq!([(); 4].map(|_| reader.next(0)), [1.0, 0.0, -1.0, -2.0]);
+    }
+
+    #[test]
+    fn an_unchanged_value_does_not_restart_the_ramp() {
+        let params = Params::new([0.0]);
+        let mut reader = ParamReader::new(&params, [4]);
+        params.set(0, 4.0);
+        reader.begin_block();
+        reader.advance(0, 2);
+        reader.begin_block();
+        assert_eq!(reader.advance(0, 2), 4.0);
+    }
+
+    #[test]
+    fn non_finite_values_are_ignored() {
+        let params = Params::new([f32::NAN, 1.0]);
+        let mut reader = ParamReader::new(&params, [4, 4]);
+        assert_eq!(reader.next(0), 0.0);
+
+        params.set(1, 3.0);
+        reader.begin_block();
+        assert_eq!(reader.next(1), 1.5);
+        for value in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            params.set(1, value);
+            reader.begin_block();
+            assert_eq!(reader.target(1), 3.0);
+        }
+        assert_eq!([(); 3].map(|_| reader.next(1)), [2.0, 2.5, 3.0]);
+
+        params.set(0, 2.0);
+        reader.begin_block();
+        assert_eq!(reader.advance(0, 4), 2.0);
+    }
+}
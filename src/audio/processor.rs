@@ -0,0 +1,301 @@
+//! The `AudioProcessor` trait, an alternative to passing a closure to `Interface::start_callback`.
+//!
+//! Nothing in here touches the hardware, so processors can be tested on the host by calling
+//! `prepare()` and `process()` directly.
+use super::Fs;
+use super::sample::{self, Frame, Sample};
+
+/// A block-based audio effect or generator driven by `Interface::run_processor`.
+///
+/// `S` is the sample type of the frames passed to `process()`, see [`sample`].
+pub trait AudioProcessor<S: Sample = f32> {
+    /// Called before the first block, every time `run_processor()` is entered.
+    /// Enter it again after `set_sample_rate()` so the processor sees the new rate.
+    ///
+    /// A processor whose buffers are too small for `block_size` frames returns
+    /// `PrepareError::BlockTooLong`, which `run_processor()` returns before any block is processed.
+    fn prepare(&mut self, _fs: Fs, _block_size: usize) -> Result<(), PrepareError> {
+        Ok(())
+    }
+
+    /// Processes one block. `input` and `output` hold `block_size` frames.
+    fn process(&mut self, input: &[Frame<S>], output: &mut [Frame<S>]);
+
+    /// Clears the processing state (delay lines, envelopes, ...).
+    /// Called by `run_processor()` right after `prepare()`.
+    fn reset(&mut self) {}
+
+    /// Called after an SAI overrun or underrun. The input has a gap, and unless the interface
+    /// recovers by itself (see `RecoveryPolicy`), `run_processor()` returns with the error.
+    fn on_xrun(&mut self) {}
+}
+
+/// Why `AudioProcessor::prepare` rejected a block size.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum PrepareError {
+    /// The processor holds at most `max` frames per block, e.g. the `N` of a `Chain`.
+    BlockTooLong { max: usize },
+}
+
+/// `Err(PrepareError::BlockTooLong)` if `block_size` is longer than `max`.
+pub(crate) fn check_block_size(block_size: usize, max: usize) -> Result<(), PrepareError> {
+    if block_size > max {
+        return Err(PrepareError::BlockTooLong { max });
+    }
+    Ok(())
+}
+
+impl<S: Sample, P: AudioProcessor<S> + ?Sized> AudioProcessor<S> for &mut P {
+    fn prepare(&mut self, fs: Fs, block_size: usize) -> Result<(), PrepareError> {
+        (**self).prepare(fs, block_size)
+    }
+
+    fn process(&mut self, input: &[Frame<S>], output: &mut [Frame<S>]) {
+        (**self).process(input, output)
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+
+    fn on_xrun(&mut self) {
+        (**self).on_xrun()
+    }
+}
+
+/// Runs `first`, then `second` on its output. `N` is the block length, or an upper bound of it:
+/// `prepare()` rejects longer blocks.
+pub struct Chain<A, B, S, const N: usize> {
+    pub first: A,
+    pub second: B,
+    scratch: [Frame<S>; N],
+}
+
+impl<A, B, S: Sample, const N: usize> Chain<A, B, S, N> {
+    pub fn new(first: A, second: B) -> Self {
+        Self {
+            first,
+            second,
+            scratch: [[S::default(); sample::CHANNELS]; N],
+        }
+    }
+}
+
+impl<A, B, S, const N: usize> AudioProcessor<S> for Chain<A, B, S, N>
+where
+    A: AudioProcessor<S>,
+    B: AudioProcessor<S>,
+    S: Sample,
+{
+    fn prepare(&mut self, fs: Fs, block_size: usize) -> Result<(), PrepareError> {
+        check_block_size(block_size, N)?;
+        self.first.prepare(fs, block_size)?;
+        self.second.prepare(fs, block_size)
+    }
+
+    fn process(&mut self, input: &[Frame<S>], output: &mut [Frame<S>]) {
+        let scratch = &mut self.scratch[..input.len()];
+        self.first.process(input, scratch);
+        self.second.process(scratch, output);
+    }
+
+    fn reset(&mut self) {
+        self.first.reset();
+        self.second.reset();
+    }
+
+    fn on_xrun(&mut self) {
+        self.first.on_xrun();
+        self.second.on_xrun();
+    }
+}
+
+/// What the callback loop of `Interface` drives: raw SAI words in and out.
+pub(crate) trait RawProcessor {
+    fn process_raw(&mut self, input: &[u32], output: &mut [u32]);
+
+    fn on_xrun(&mut self) {}
+}
+
+impl<F: FnMut(&[u32], &mut [u32])> RawProcessor for F {
+    fn process_raw(&mut self, input: &[u32], output: &mut [u32]) {
+        self(input, output)
+    }
+}
+
+/// Converts raw SAI words to frames of `S` for an `AudioProcessor`.
+pub(crate) struct Converter<P, S, const N: usize> {
+    processor: P,
+    input: [Frame<S>; N],
+    output: [Frame<S>; N],
+}
+
+impl<P, S: Sample, const N: usize> Converter<P, S, N> {
+    pub(crate) fn new(processor: P) -> Self {
+        Self {
+            processor,
+            input: [[S::default(); sample::CHANNELS]; N],
+            output: [[S::default(); sample::CHANNELS]; N],
+        }
+    }
+}
+
+impl<P: AudioProcessor<S>, S: Sample, const N: usize> RawProcessor for Converter<P, S, N> {
+    fn process_raw(&mut self, input: &[u32], output: &mut [u32]) {
+        sample::decode(input, &mut self.input);
+        self.processor.process(&self.input, &mut self.output);
+        sample::encode(&self.output, output);
+    }
+
+    fn on_xrun(&mut self) {
+        self.processor.on_xrun()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Adds `offset` to every sample and records the calls it gets.
+    #[derive(Default)]
+    struct Offset {
+        offset: i32,
+        prepared: Option<(Fs, usize)>,
+        resets: u32,
+        xruns: u32,
+    }
+
+    impl Offset {
+        fn new(offset: i32) -> Self {
+            Self {
+                offset,
+                ..Default::default()
+            }
+        }
+    }
+
+    impl AudioProcessor<i32> for Offset {
+        fn prepare(&mut self, fs: Fs, block_size: usize) -> Result<(), PrepareError> {
+            self.prepared = Some((fs, block_size));
+            Ok(())
+        }
+
+        fn process(&mut self, input: &[Frame<i32>], output: &mut [Frame<i32>]) {
+            for (o, i) in output.iter_mut().zip(input) {
+                *o = [i[0] + self.offset, i[1] + self.offset];
+            }
+        }
+
+        fn reset(&mut self) {
+            self.resets += 1;
+        }
+
+        fn on_xrun(&mut self) {
+            self.xruns += 1;
+        }
+    }
+
+    /// Doubles every sample.
+    struct Double;
+
+    impl AudioProcessor<i32> for Double {
+        fn process(&mut self, input: &[Frame<i32>], output: &mut [Frame<i32>]) {
+            for (o, i) in output.iter_mut().zip(input) {
+                *o = [i[0] * 2, i[1] * 2];
+            }
+        }
+    }
+
+    #[test]
+    fn chain_runs_first_then_second() {
+        let mut chain = Chain::<_, _, i32, 4>::new(Offset::new(1), Double);
+        let input = [[1, -1], [2, -2], [3, -3], [4, -4]];
+        let mut output = [[0; 2]; 4];
+        chain.process(&input, &mut output);
+        assert_eq!(output, [[4, 0], [6, -2], [8, -4], [10, -6]]);
+
+        let mut reversed = Chain::<_, _, i32, 4>::new(Double, Offset::new(1));
+        reversed.process(&input, &mut output);
+        assert_eq!(output, [[3, -1], [5, -3], [7, -5], [9, -7]]);
+    }
+
+    #[test]
+    fn chain_takes_blocks_shorter_than_n() {
+        let mut chain = Chain::<_, _, i32, 8>::new(Offset::new(1), Offset::new(10));
+        assert_eq!(chain.prepare(Fs::Fs48000, 2), Ok(()));
+        let mut output = [[0; 2]; 2];
+        chain.process(&[[0, 1], [2, 3]], &mut output);
+        assert_eq!(output, [[11, 12], [13, 14]]);
+    }
+
+    #[test]
+    fn chain_rejects_blocks_longer_than_n() {
+        let mut chain = Chain::<_, _, i32, 4>::new(Offset::new(1), Offset::new(2));
+        assert_eq!(
+            chain.prepare(Fs::Fs48000, 32),
+            Err(PrepareError::BlockTooLong { max: 4 })
+        );
+        assert_eq!(chain.first.prepared, None);
+        assert_eq!(chain.prepare(Fs::Fs48000, 4), Ok(()));
+    }
+
+    #[test]
+    fn chain_forwards_the_lifecycle_to_both() {
+        let mut chain = Chain::<_, _, i32, 4>::new(Offset::new(1), Offset::new(2));
+        chain.prepare(Fs::Fs96000, 4).unwrap();
+        chain.reset();
+        chain.on_xrun();
+        for processor in [&chain.first, &chain.second] {
+            assert_eq!(processor.prepared, Some((Fs::Fs96000, 4)));
+            assert_eq!(processor.resets, 1);
+            assert_eq!(processor.xruns, 1);
+        }
+    }
+
+    #[test]
+    fn mutable_references_forward_to_the_processor() {
+        fn drive(mut processor: impl AudioProcessor<i32>) -> [Frame<i32>; 1] {
+            processor.prepare(Fs::Fs44100, 16).unwrap();
+            processor.reset();
+            processor.on_xrun();
+            let mut output = [[0; 2]];
+            processor.process(&[[1, 2]], &mut output);
+            output
+        }
+
+        let mut offset = Offset::new(5);
+        assert_eq!(drive(&mut offset), [[6, 7]]);
+        assert_eq!(offset.prepared, Some((Fs::Fs44100, 16)));
+        assert_eq!((offset.resets, offset.xruns), (1, 1));
+    }
+
+    #[test]
+    fn converter_decodes_processes_and_encodes() {
+        let mut converter = Converter::<_, i32, 2>::new(Double);
+        let input = [100, -100, 7, -3].map(sample::i24_to_raw);
+        let mut output = [0; 4];
+        converter.process_raw(&input, &mut output);
+        assert_eq!(output.map(sample::raw_to_i24), [200, -200, 14, -6]);
+    }
+
+    #[test]
+    fn converter_forwards_xruns() {
+        let mut converter = Converter::<_, i32, 2>::new(Offset::new(0));
+        converter.on_xrun();
+        assert_eq!(converter.processor.xruns, 1);
+    }
+
+    #[test]
+    fn closures_are_raw_processors() {
+        let mut calls = 0;
+        let mut closure = |input: &[u32], output: &mut [u32]| {
+            calls += 1;
+            output.copy_from_slice(input);
+        };
+        let mut output = [0; 2];
+        closure.process_raw(&[1, 2], &mut output);
+        closure.on_xrun();
+        assert_eq!(output, [1, 2]);
+        assert_eq!(calls, 1);
+    }
+}
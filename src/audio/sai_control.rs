@@ -0,0 +1,71 @@
+//! Direct SAI register access for what `embassy_stm32::sai::Sai` does not offer,
+//! like pausing running sub-blocks or changing the master clock divider.
+//!
+//! Every codec in this crate runs sub-block A as the clock master (with MCLK) and
+//! sub-block B synchronous to it, so these helpers always act on both.
+use super::ClockRatio;
+use embassy_stm32::pac::sai::Sai as Regs;
+use embassy_stm32::sai::MasterClockDivider;
+
+const MASTER: usize = 0; // sub-block A
+const SLAVE: usize = 1; // sub-block B
+
+/// Stops both sub-blocks at the end of the current frame.
+/// DMA stays configured, so `resume` continues exactly where the ring buffers left off.
+pub(crate) fn pause(regs: Regs) {
+    for sub_block in [SLAVE, MASTER] {
+        let ch = regs.ch(sub_block);
+        ch.cr1().modify(|w| w.set_saien(false));
+        // SAIEN reads back as set until the current frame has been completed.
+        while ch.cr1().read().saien() {}
+    }
+}
+
+/// Restarts sub-blocks stopped by `pause`. The slave is enabled first so that it
+/// does not miss the first frame clocked out by the master.
+pub(crate) fn resume(regs: Regs) {
+    resume_all(&[regs]);
+}
+
+/// Same as `resume` for several SAIs: all slaves first, then all masters in consecutive
+/// writes with interrupts disabled. SAIs running from the same kernel clock and master clock
+/// divider then start their frames together, to within one master clock period.
+pub(crate) fn resume_all(regs: &[Regs]) {
+    cortex_m::interrupt::free(|_| {
+        for sub_block in [SLAVE, MASTER] {
+            for regs in regs {
+                regs.ch(sub_block).cr1().modify(|w| w.set_saien(true));
+            }
+        }
+    });
+}
+
+/// Must only be called while paused.
+pub(crate) fn set_master_clock_divider(regs: Regs, divider: MasterClockDivider) {
+    regs.ch(MASTER).cr1().modify(|w| w.set_mckdiv(divider));
+}
+
+/// Starts or stops the MCLK output of the master sub-block. Must only be called while paused.
+pub(crate) fn set_master_clock_enabled(regs: Regs, enabled: bool) {
+    regs.ch(MASTER).cr1().modify(|w| w.set_mcken(enabled));
+}
+
+/// Selects 256 x fs (OSR = 0) or 512 x fs (OSR = 1) for the master clock output.
+/// `sai::Config` has no field for this, so the bit is written directly. Must only be called while disabled.
+pub(crate) fn set_clock_ratio(regs: Regs, clock_ratio: ClockRatio) {
+    const OSR: u32 = 1 << 26; // SAI_xCR1.OSR
+    regs.ch(MASTER).cr1().modify(|w| match clock_ratio {
+        ClockRatio::Ratio256 => w.0 &= !OSR,
+        ClockRatio::Ratio512 => w.0 |= OSR,
+    });
+}
+
+/// Sets the frame length in bit clocks on both sub-blocks. `sai::Config::frame_length` is a `u8`,
+/// which cannot describe the 256-bit frames of 8-slot TDM. Must only be called while disabled.
+pub(crate) fn set_frame_length(regs: Regs, frame_length: u16) {
+    for sub_block in [SLAVE, MASTER] {
+        regs.ch(sub_block)
+            .frcr()
+            .modify(|w| w.set_frl((frame_length - 1) as u8));
+    }
+}
@@ -0,0 +1,247 @@
+//! Conversion between raw SAI words and typed audio samples.
+//!
+//! Every supported codec (AK4556, WM8731, PCM3060) is driven with 24-bit
+//! left-justified data, and the SAI is configured with `DataSize::Data24`.
+//! Each sample therefore travels as a 24-bit two's-complement value in the
+//! lower 24 bits of a `u32` word. The upper 8 bits are ignored on transmit and
+//! are not guaranteed to hold a sign extension on receive, so decoding always
+//! sign-extends from bit 23.
+
+/// Number of interleaved channels in a frame.
+pub const CHANNELS: usize = 2;
+
+/// One stereo frame: `[left, right]`.
+pub type Frame<S> = [S; CHANNELS];
+
+const FULL_SCALE: f32 = 8_388_608.0; // 2^23
+
+/// A sample type that can be converted to and from raw SAI words.
+pub trait Sample: Copy + Default {
+    /// Decode a raw SAI word.
+    fn from_raw(raw: u32) -> Self;
+    /// Encode into a raw SAI word, clipping to the 24-bit range if needed.
+    fn to_raw(self) -> u32;
+}
+
+/// Normalized sample in `[-1.0, 1.0)`. Out-of-range values are clipped.
+impl Sample for f32 {
+    #[inline(always)]
+    fn from_raw(raw: u32) -> Self {
+        raw_to_i24(raw) as f32 / FULL_SCALE
+    }
+
+    #[inline(always)]
+    fn to_raw(self) -> u32 {
+        let x = (self * FULL_SCALE).clamp(-FULL_SCALE, FULL_SCALE - 1.0);
+        i24_to_raw(x as i32)
+    }
+}
+
+/// Full-scale 32-bit sample. The lowest 8 bits are dropped on transmit.
+impl Sample for i32 {
+    #[inline(always)]
+    fn from_raw(raw: u32) -> Self {
+        raw_to_i24(raw) << 8
+    }
+
+    #[inline(always)]
+    fn to_raw(self) -> u32 {
+        i24_to_raw(self >> 8)
+    }
+}
+
+/// Full-scale 16-bit sample. The lowest 8 bits of the codec data are dropped on receive.
+impl Sample for i16 {
+    #[inline(always)]
+    fn from_raw(raw: u32) -> Self {
+        (raw_to_i24(raw) >> 8) as i16
+    }
+
+    #[inline(always)]
+    fn to_raw(self) -> u32 {
+        i24_to_raw((self as i32) << 8)
+    }
+}
+
+/// Sign-extend the 24-bit two's-complement value held in the lower bits of `raw`.
+#[inline(always)]
+pub const fn raw_to_i24(raw: u32) -> i32 {
+    ((raw << 8) as i32) >> 8
+}
+
+/// Pack a 24-bit two's-complement value into a raw SAI word.
+/// Values outside the 24-bit range wrap, so clip before calling this.
+#[inline(always)]
+pub const fn i24_to_raw(value: i32) -> u32 {
+    (value as u32) & 0x00FF_FFFF
+}
+
+/// Decode an interleaved block of raw words into frames.
+/// Converts `min(raw.len() / CHANNELS, frames.len())` frames.
+pub fn decode<S: Sample>(raw: &[u32], frames: &mut [Frame<S>]) {
+    for (frame, words) in frames.iter_mut().zip(raw.chunks_exact(CHANNELS)) {
+        for (s, w) in frame.iter_mut().zip(words) {
+            *s = S::from_raw(*w);
+        }
+    }
+}
+
+/// Encode frames into an interleaved block of raw words.
+/// Converts `min(frames.len(), raw.len() / CHANNELS)` frames.
+pub fn encode<S: Sample>(frames: &[Frame<S>], raw: &mut [u32]) {
+    for (frame, words) in frames.iter().zip(raw.chunks_exact_mut(CHANNELS)) {
+        for (s, w) in frame.iter().zip(words) {
+            *w = s.to_raw();
+        }
+    }
+}
+
+/// Split an interleaved block of raw words into one buffer per channel.
+/// Converts `min(raw.len() / CHANNELS, N)` frames.
+pub fn deinterleave<S: Sample, const N: usize>(raw: &[u32], planar: &mut [[S; N]; CHANNELS]) {
+    for (i, words) in raw.chunks_exact(CHANNELS).take(N).enumerate() {
+        for (channel, w) in planar.iter_mut().zip(words) {
+            channel[i] = S::from_raw(*w);
+        }
+    }
+}
+
+/// Merge one buffer per channel into an interleaved block of raw words.
+/// Converts `min(N, raw.len() / CHANNELS)` frames.
+pub fn interleave<S: Sample, const N: usize>(planar: &[[S; N]; CHANNELS], raw: &mut [u32]) {
+    for (i, words) in raw.chunks_exact_mut(CHANNELS).take(N).enumerate() {
+        for (channel, w) in planar.iter().zip(words) {
+            *w = channel[i].to_raw();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX: u32 = 0x7F_FFFF; // largest positive 24-bit value
+    const MIN: u32 = 0x80_0000; // most negative 24-bit value
+
+    #[test]
+    fn raw_to_i24_sign_extends_from_bit_23() {
+        assert_eq!(raw_to_i24(0), 0);
+        assert_eq!(raw_to_i24(1), 1);
+        assert_eq!(raw_to_i24(MAX), 8_388_607);
+        assert_eq!(raw_to_i24(MIN), -8_388_608);
+        assert_eq!(raw_to_i24(0x00FF_FFFF), -1);
+        // the upper 8 bits are ignored, whatever the SAI left there
+        assert_eq!(raw_to_i24(0xFF00_0001), 1);
+        assert_eq!(raw_to_i24(0x0080_0000 | 0xAB00_0000), -8_388_608);
+        assert_eq!(raw_to_i24(0xFFFF_FFFF), -1);
+    }
+
+    #[test]
+    fn i24_to_raw_keeps_the_lower_24_bits() {
+        assert_eq!(i24_to_raw(-1), 0x00FF_FFFF);
+        assert_eq!(i24_to_raw(-8_388_608), MIN);
+        assert_eq!(i24_to_raw(8_388_607), MAX);
+        for raw in [0, 1, 0x12_3456, MAX, MIN, 0xFF_FFFF] {
+            assert_eq!(i24_to_raw(raw_to_i24(raw)), raw);
+        }
+    }
+
+    #[test]
+    fn f32_round_trip() {
+        for raw in [0, 1, 0x12_3456, MAX, MIN, 0xED_CBAA, 0xFF_FFFF] {
+            assert_eq!(f32::from_raw(raw).to_raw(), raw);
+        }
+        assert_eq!(f32::from_raw(MIN), -1.0);
+        assert_eq!(f32::from_raw(0x40_0000), 0.5);
+        assert_eq!(f32::from_raw(0xC0_0000), -0.5);
+        assert!(f32::from_raw(MAX) < 1.0);
+    }
+
+    #[test]
+    fn f32_clips_at_full_scale() {
+        assert_eq!(1.0f32.to_raw(), MAX);
+        assert_eq!(1.5f32.to_raw(), MAX);
+        assert_eq!(f32::INFINITY.to_raw(), MAX);
+        assert_eq!((-1.0f32).to_raw(), MIN);
+        assert_eq!((-1.5f32).to_raw(), MIN);
+        assert_eq!(f32::NEG_INFINITY.to_raw(), MIN);
+    }
+
+    #[test]
+    fn i32_round_trip() {
+        for raw in [0, 1, 0x12_3456, MAX, MIN, 0xFF_FFFF] {
+            assert_eq!(i32::from_raw(raw).to_raw(), raw);
+        }
+        assert_eq!(i32::from_raw(MAX), 0x7FFF_FF00);
+        assert_eq!(i32::from_raw(MIN), i32::MIN);
+        assert_eq!(i32::from_raw(0xFF_FFFF), -256);
+    }
+
+    #[test]
+    fn i32_extremes_map_to_24_bit_limits() {
+        assert_eq!(i32::MAX.to_raw(), MAX);
+        assert_eq!(i32::MIN.to_raw(), MIN);
+        // the lowest 8 bits are dropped, not rounded
+        assert_eq!(0x1234_56FF.to_raw(), 0x12_3456);
+        assert_eq!((-1i32).to_raw(), 0xFF_FFFF);
+    }
+
+    #[test]
+    fn i16_round_trip() {
+        for sample in [0, 1, -1, 1234, -1234, i16::MAX, i16::MIN] {
+            assert_eq!(i16::from_raw(sample.to_raw()), sample);
+        }
+        assert_eq!(i16::MAX.to_raw(), 0x7F_FF00);
+        assert_eq!(i16::MIN.to_raw(), MIN);
+        assert_eq!(i16::from_raw(MAX), i16::MAX);
+        assert_eq!(i16::from_raw(MIN), i16::MIN);
+        // the lowest 8 bits of the codec data are dropped
+        assert_eq!(i16::from_raw(0x00_01FF), 1);
+    }
+
+    #[test]
+    fn decode_and_encode_interleaved_frames() {
+        let raw = [0x40_0000, 0xC0_0000, MAX, MIN];
+        let mut frames = [[0.0f32; CHANNELS]; 2];
+        decode(&raw, &mut frames);
+        assert_eq!(frames[0], [0.5, -0.5]);
+        assert_eq!(frames[1][1], -1.0);
+
+        let mut encoded = [0; 4];
+        encode(&frames, &mut encoded);
+        assert_eq!(encoded, raw);
+    }
+
+    #[test]
+    fn deinterleave_splits_channels() {
+        let raw = [1, 0xFF_FFFF, 2, 0xFF_FFFE, 3, 0xFF_FFFD];
+        let mut planar = [[0i32; 3]; CHANNELS];
+        deinterleave(&raw, &mut planar);
+        assert_eq!(
+            planar,
+            [[1 << 8, 2 << 8, 3 << 8], [-1 << 8, -2 << 8, -3 << 8]]
+        );
+    }
+
+    #[test]
+    fn interleave_is_the_inverse_of_deinterleave() {
+        let raw = [0x10, 0x20, 0x11, 0x21, 0x12, 0x22, MAX, MIN];
+        let mut planar = [[0.0f32; 4]; CHANNELS];
+        deinterleave(&raw, &mut planar);
+        let mut interleaved = [0; 8];
+        interleave(&planar, &mut interleaved);
+        assert_eq!(interleaved, raw);
+    }
+
+    #[test]
+    fn interleave_converts_only_whole_frames_that_fit() {
+        let planar = [[1i16, 2, 3], [-1, -2, -3]];
+        let mut raw = [0xAAAA_AAAA; 5];
+        interleave(&planar, &mut raw);
+        assert_eq!(raw, [0x100, 0xFF_FF00, 0x200, 0xFF_FE00, 0xAAAA_AAAA]);
+
+        let mut planar = [[0i16; 3]; CHANNELS];
+        deinterleave(&raw[..4], &mut planar);
+        assert_eq!(planar, [[1, 2, 0], [-1, -2, 0]]);
+    }
+}
@@ -0,0 +1,235 @@
+//! A second codec on SAI2, for 4-in/4-out operation. See `Interface::attach_secondary`.
+//!
+//! The second codec is expected to be configured by hardware pins, like the PCM3060 on Seed 1.2
+//! or the AK4556 on Daisy Patch/Field: SAI2 sub-block A transmits and drives MCLK, SCK and FS,
+//! sub-block B receives synchronously, 24-bit left-justified.
+//! With `Framing::Tdm`, the same pins carry a TDM frame of 4 or 8 slots instead.
+//!
+//! SAI2 runs from the same kernel clock (PLL3) and master clock divider as SAI1, and `Interface`
+//! enables both in the same instant (`sai_control::resume_all`), so their frames start together
+//! to within one master clock period and stay locked. SAI2 is not synchronised through the SAI
+//! sync input instead: a synchronous sub-block releases its MCLK, SCK and FS pins, and those
+//! clock the second codec.
+use embassy_stm32::{self as hal, Peri, bind_interrupts, dma, peripherals, sai};
+use hal::peripherals::*;
+
+use super::{AudioConfig, Framing, SILENCE, sai_control, scale};
+
+bind_interrupts!(pub struct SecondaryAudioIrqs{
+    DMA1_STREAM2 => dma::InterruptHandler<peripherals::DMA1_CH2>;
+    DMA1_STREAM3 => dma::InterruptHandler<peripherals::DMA1_CH3>;
+});
+
+/// SAI2 pins, D24 to D28 on the Seed.
+#[allow(non_snake_case)]
+pub struct Sai2Pins<'a> {
+    pub MCLK_A: Peri<'a, PA1>, // D24, SAI2 MCLK_A
+    pub SD_B: Peri<'a, PA0>,   // D25, SAI2 SD_B
+    pub SD_A: Peri<'a, PD11>,  // D26, SAI2 SD_A
+    pub FS_A: Peri<'a, PG9>,   // D27, SAI2 FS_A
+    pub SCK_A: Peri<'a, PA2>,  // D28, SAI2 SCK_A
+}
+
+/// Peripherals for the second codec. Not part of `DaisyBoard`, because the pins are shared
+/// with the general purpose pins:
+/// ```rust
+/// let secondary = SecondaryAudioPeripherals {
+///     pins: Sai2Pins {
+///         MCLK_A: board.pins.d24,
+///         SD_B: board.pins.d25,
+///         SD_A: board.pins.d26,
+///         FS_A: board.pins.d27,
+///         SCK_A: board.pins.d28,
+///     },
+///     sai2: p.SAI2,
+///     dma1_ch2: p.DMA1_CH2,
+///     dma1_ch3: p.DMA1_CH3,
+/// };
+/// ```
+pub struct SecondaryAudioPeripherals<'a> {
+    pub pins: Sai2Pins<'a>,
+    pub sai2: Peri<'a, peripherals::SAI2>,
+    pub dma1_ch2: Peri<'a, peripherals::DMA1_CH2>,
+    pub dma1_ch3: Peri<'a, peripherals::DMA1_CH3>,
+}
+
+pub(crate) struct Secondary<'a> {
+    sai_tx: sai::Sai<'a, peripherals::SAI2, u32>,
+    sai_rx: sai::Sai<'a, peripherals::SAI2, u32>,
+    half_dma_buffer_length: usize,
+    channels: usize,
+    // started before and paused since
+    started: bool,
+}
+
+impl<'a> Secondary<'a> {
+    pub(crate) fn new(
+        p: SecondaryAudioPeripherals<'a>,
+        audio_config: AudioConfig,
+        tx_buffer: &'a mut [u32],
+        rx_buffer: &'a mut [u32],
+    ) -> Self {
+        let half_dma_buffer_length = tx_buffer.len() / 2;
+
+        let (sub_block_tx, sub_block_rx) = sai::split_subblocks(p.sai2);
+        let mut sai_tx_config = sai::Config::default();
+        sai_tx_config.mode = sai::Mode::Master;
+        sai_tx_config.tx_rx = sai::TxRx::Transmitter;
+        sai_tx_config.clock_strobe = sai::ClockStrobe::Falling;
+        sai_tx_config.master_clock_divider = audio_config.master_clock_divider();
+        sai_tx_config.stereo_mono = sai::StereoMono::Stereo;
+        sai_tx_config.data_size = sai::DataSize::Data24;
+        sai_tx_config.bit_order = sai::BitOrder::MsbFirst;
+        sai_tx_config.frame_sync_polarity = sai::FrameSyncPolarity::ActiveHigh;
+        sai_tx_config.frame_sync_offset = sai::FrameSyncOffset::OnFirstBit;
+        sai_tx_config.frame_length = 64;
+        sai_tx_config.frame_sync_active_level_length = sai::word::U7(32);
+        sai_tx_config.fifo_threshold = sai::FifoThreshold::Quarter;
+        if let Framing::Tdm {
+            slots,
+            active_slots,
+        } = audio_config.framing
+        {
+            // `frame_length` is a u8: 256-bit frames are fixed up by `sai_control::set_frame_length`.
+            sai_tx_config.frame_length =
+                u8::try_from(audio_config.framing.frame_length()).unwrap_or(u8::MAX);
+            sai_tx_config.frame_sync_definition = sai::FrameSyncDefinition::StartOfFrame;
+            sai_tx_config.frame_sync_offset = sai::FrameSyncOffset::BeforeFirstBit;
+            sai_tx_config.frame_sync_active_level_length = sai::word::U7(1);
+            sai_tx_config.slot_size = sai::SlotSize::Channel32;
+            sai_tx_config.slot_count = sai::word::U4(slots.count() as u8);
+            sai_tx_config.slot_enable = active_slots;
+        }
+
+        let mut sai_rx_config = sai_tx_config;
+        sai_rx_config.mode = sai::Mode::Slave;
+        sai_rx_config.tx_rx = sai::TxRx::Receiver;
+        sai_rx_config.sync_input = sai::SyncInput::Internal;
+        sai_rx_config.clock_strobe = sai::ClockStrobe::Rising;
+
+        let sai_tx = sai::Sai::new_asynchronous_with_mclk(
+            sub_block_tx,
+            p.pins.SCK_A,
+            p.pins.SD_A,
+            p.pins.FS_A,
+            p.pins.MCLK_A,
+            p.dma1_ch2,
+            tx_buffer,
+            SecondaryAudioIrqs,
+            sai_tx_config,
+        );
+        let sai_rx = sai::Sai::new_synchronous(
+            sub_block_rx,
+            p.pins.SD_B,
+            p.dma1_ch3,
+            rx_buffer,
+            SecondaryAudioIrqs,
+            sai_rx_config,
+        );
+
+        Self {
+            sai_tx,
+            sai_rx,
+            half_dma_buffer_length,
+            channels: audio_config.framing.channels(),
+            started: false,
+        }
+    }
+
+    /// Same as the codecs' `start()`: prefilling the transmitter starts the master sub-block.
+    /// SAI2 is paused again right away, for `Interface` to resume it together with SAI1.
+    /// Does nothing once started.
+    pub(crate) async fn start(&mut self) -> Result<(), sai::Error> {
+        if self.started {
+            return Ok(());
+        }
+        self.write_silence(self.half_dma_buffer_length).await?;
+        self.sai_rx.start()?;
+        self.started = true;
+        sai_control::pause(hal::pac::SAI2);
+        Ok(())
+    }
+
+    /// Writes `frames` frames of silence while draining as many input frames, like `Interface::transfer`.
+    pub(crate) async fn transfer_silence(&mut self, frames: usize) -> Result<(), sai::Error> {
+        let length = frames * self.channels;
+        self.drain(length).await?;
+        self.write_silence(length).await
+    }
+
+    /// Like `transfer_silence`, but ramps each channel down from `last`, one word per channel,
+    /// over `frames` frames, as `Interface::fade_out` does for SAI1.
+    pub(crate) async fn transfer_fade_out(
+        &mut self,
+        last: &[u32],
+        frames: usize,
+    ) -> Result<(), sai::Error> {
+        let length = frames * self.channels;
+        self.drain(length).await?;
+        let mut scratch = [0; 64];
+        let mut written = 0;
+        while written < length {
+            let chunk_length = (length - written).min(scratch.len());
+            let chunk = &mut scratch[..chunk_length];
+            for (k, w) in chunk.iter_mut().enumerate() {
+                let frame = (written + k) / self.channels;
+                let channel = (written + k) % self.channels;
+                *w = scale(last[channel], frames - 1 - frame, frames);
+            }
+            self.sai_tx.write(chunk).await?;
+            written += chunk.len();
+        }
+        Ok(())
+    }
+
+    /// Reads and discards `length` words of input.
+    async fn drain(&mut self, length: usize) -> Result<(), sai::Error> {
+        let mut scratch = [0; 64];
+        let mut remaining = length;
+        while remaining > 0 {
+            let chunk = remaining.min(scratch.len());
+            self.sai_rx.read(&mut scratch[..chunk]).await?;
+            remaining -= chunk;
+        }
+        Ok(())
+    }
+
+    /// TDM blocks can be larger than `SILENCE`, so this writes it in chunks.
+    async fn write_silence(&mut self, mut remaining: usize) -> Result<(), sai::Error> {
+        while remaining > 0 {
+            let length = remaining.min(SILENCE.len());
+            self.sai_tx.write(&SILENCE[..length]).await?;
+            remaining -= length;
+        }
+        Ok(())
+    }
+
+    /// Words per frame in the DMA buffers, see `Framing::channels`.
+    pub(crate) fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub(crate) fn pause(&mut self) {
+        if self.started {
+            sai_control::pause(hal::pac::SAI2);
+        }
+    }
+
+    /// Must only be called while paused.
+    pub(crate) fn set_master_clock_enabled(&mut self, enabled: bool) {
+        sai_control::set_master_clock_enabled(hal::pac::SAI2, enabled);
+    }
+
+    /// Must only be called while paused.
+    pub(crate) fn set_master_clock_divider(&mut self, divider: sai::MasterClockDivider) {
+        sai_control::set_master_clock_divider(hal::pac::SAI2, divider);
+    }
+
+    pub(crate) async fn read(&mut self, read_buf: &mut [u32]) -> Result<(), sai::Error> {
+        self.sai_rx.read(read_buf).await
+    }
+
+    pub(crate) async fn write(&mut self, write_buf: &[u32]) -> Result<(), sai::Error> {
+        self.sai_tx.write(write_buf).await
+    }
+}
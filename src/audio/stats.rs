@@ -0,0 +1,85 @@
+//! SAI error counters and the policy for recovering from them.
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// What `start_callback()` does when the SAI reports an overrun or underrun.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, defmt::Format)]
+pub enum RecoveryPolicy {
+    /// Return the error to the caller, which has to handle it.
+    #[default]
+    ReturnError,
+    /// Realign the DMA rings and keep the callback loop running.
+    /// The output jumps back in after a few blocks of silence.
+    Restart,
+    /// Like `Restart`, but the first block after the recovery is faded in from silence.
+    RestartWithFadeIn,
+}
+
+/// Error counters of one audio interface.
+///
+/// Counting happens regardless of the `RecoveryPolicy`. The counters are atomic,
+/// so another task can read them while the callback loop is running:
+/// ```rust
+/// let stats = interface.stats();
+/// spawner.spawn(report(stats)).unwrap();
+/// interface.start_callback(process).await;
+/// ```
+pub struct AudioStats {
+    overruns: AtomicU32,
+    underruns: AtomicU32,
+    recoveries: AtomicU32,
+}
+
+impl AudioStats {
+    pub(crate) const fn new() -> Self {
+        Self {
+            overruns: AtomicU32::new(0),
+            underruns: AtomicU32::new(0),
+            recoveries: AtomicU32::new(0),
+        }
+    }
+
+    /// Receiver overruns: input arrived faster than the callback loop read it.
+    pub fn overruns(&self) -> u32 {
+        self.overruns.load(Ordering::Relaxed)
+    }
+
+    /// Transmitter underruns: the callback loop did not write output in time.
+    pub fn underruns(&self) -> u32 {
+        self.underruns.load(Ordering::Relaxed)
+    }
+
+    /// Errors the callback loop has recovered from without returning.
+    pub fn recoveries(&self) -> u32 {
+        self.recoveries.load(Ordering::Relaxed)
+    }
+
+    pub fn reset(&self) {
+        self.overruns.store(0, Ordering::Relaxed);
+        self.underruns.store(0, Ordering::Relaxed);
+        self.recoveries.store(0, Ordering::Relaxed);
+    }
+
+    pub(crate) fn count_overrun(&self) {
+        self.overruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn count_underrun(&self) {
+        self.underruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn count_recovery(&self) {
+        self.recoveries.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl defmt::Format for AudioStats {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "AudioStats {{ overruns: {}, underruns: {}, recoveries: {} }}",
+            self.overruns(),
+            self.underruns(),
+            self.recoveries()
+        )
+    }
+}
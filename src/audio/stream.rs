@@ -0,0 +1,544 @@
+//! Block queues between the audio callback and async tasks.
+//!
+//! A `BlockQueue` is a single-producer single-consumer queue of `Q` blocks of `N` frames.
+//! Splitting it gives one endpoint for the audio task and one for another task:
+//!
+//! * `BlockQueue::split_source()`: a task writes blocks with `Producer::write().await`, and the
+//!   `AudioSource` plays them. When the queue runs empty, the `UnderrunPolicy` decides what plays.
+//! * `BlockQueue::split_sink()`: the `AudioSink` records input blocks, and a task reads them with
+//!   `Consumer::read().await`. When the queue is full, the `OverrunPolicy` decides which block is
+//!   dropped.
+//!
+//! `AudioSource` and `AudioSink` implement `AudioProcessor`, so they attach to `Interface`
+//! through `run_processor()`, alone or combined with `Chain`. In a closure, use their
+//! `pull()` and `push()` instead. `N` should match the block length of the interface.
+//!
+//! # Example
+//! ```rust
+//! static RECORDING: StaticCell<BlockQueue<i32, BLOCK_LENGTH, 16>> = StaticCell::new();
+//! let (sink, mut consumer) = RECORDING
+//!     .init(BlockQueue::new())
+//!     .split_sink(OverrunPolicy::DropNewest);
+//!
+//! // in a storage task
+//! let mut block = [[0; 2]; BLOCK_LENGTH];
+//! loop {
+//!     consumer.read(&mut block).await;
+//!     // ... write `block` to SD card ...
+//! }
+//!
+//! // in the audio task: record the input and pass it through
+//! audio.run_processor(sink).await;
+//! ```
+use core::cell::UnsafeCell;
+use core::future::poll_fn;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use core::task::Poll;
+
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, RawMutex};
+use embassy_sync::waitqueue::AtomicWaker;
+
+use super::processor::check_block_size;
+use super::sample::{self, Frame, Sample};
+use super::{AudioProcessor, Fs, PrepareError};
+
+/// What an `AudioSource` plays when its queue is empty.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, defmt::Format)]
+pub enum UnderrunPolicy {
+    /// Play silence.
+    #[default]
+    Silence,
+    /// Play the last block again.
+    Repeat,
+}
+
+/// Which block an `AudioSink` drops when its queue is full.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, defmt::Format)]
+pub enum OverrunPolicy {
+    /// Drop the new block: the reader gets a gap-free run of older audio, then a jump.
+    #[default]
+    DropNewest,
+    /// Drop the oldest queued block to make room: the reader stays as close to live as possible.
+    ///
+    /// The audio side then has to move the read index, so `Consumer::try_read()` copies each block
+    /// inside a critical section, which `DropNewest` does not need.
+    DropOldest,
+}
+
+/// `Q` blocks of `N` stereo frames of `S`.
+pub struct BlockQueue<S, const N: usize, const Q: usize> {
+    blocks: UnsafeCell<MaybeUninit<[[Frame<S>; N]; Q]>>,
+    // Both indices count up modulo `2 * Q`, so that a full queue (`Q` apart) and an empty one
+    // (equal) can be told apart, and the slot `index % Q` does not jump at the wrap for any `Q`.
+    read: AtomicUsize,
+    write: AtomicUsize,
+    xruns: AtomicU32,
+    // set for `OverrunPolicy::DropOldest`: reads and drops then take `lock`
+    drop_oldest: AtomicBool,
+    lock: CriticalSectionRawMutex,
+    readable: AtomicWaker,
+    writable: AtomicWaker,
+}
+
+// SAFETY: the producer only writes slots the consumer has released and vice versa,
+// see `Producer::try_write` and `Consumer::try_read`.
+unsafe impl<S: Send, const N: usize, const Q: usize> Sync for BlockQueue<S, N, Q> {}
+
+impl<S: Sample, const N: usize, const Q: usize> Default for BlockQueue<S, N, Q> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: Sample, const N: usize, const Q: usize> BlockQueue<S, N, Q> {
+    pub const fn new() -> Self {
+        const { assert!(Q > 0, "a block queue needs room for at least one block") };
+        Self {
+            // zeroed rather than uninit, so slots can be borrowed before they are first written
+            blocks: UnsafeCell::new(MaybeUninit::zeroed()),
+            read: AtomicUsize::new(0),
+            write: AtomicUsize::new(0),
+            xruns: AtomicU32::new(0),
+            drop_oldest: AtomicBool::new(false),
+            lock: CriticalSectionRawMutex::new(),
+            readable: AtomicWaker::new(),
+            writable: AtomicWaker::new(),
+        }
+    }
+
+    /// For playback: a task writes blocks, the audio task plays them.
+    pub fn split_source(
+        &mut self,
+        policy: UnderrunPolicy,
+    ) -> (Producer<'_, S, N, Q>, AudioSource<'_, S, N, Q>) {
+        let (producer, consumer) = self.split();
+        let source = AudioSource {
+            consumer,
+            policy,
+            last: [[S::default(); sample::CHANNELS]; N],
+        };
+        (producer, source)
+    }
+
+    /// For recording: the audio task records blocks, a task reads them.
+    pub fn split_sink(
+        &mut self,
+        policy: OverrunPolicy,
+    ) -> (AudioSink<'_, S, N, Q>, Consumer<'_, S, N, Q>) {
+        let (producer, consumer) = self.split();
+        producer
+            .queue
+            .drop_oldest
+            .store(policy == OverrunPolicy::DropOldest, Ordering::Relaxed);
+        (AudioSink { producer, policy }, consumer)
+    }
+
+    /// The plain queue endpoints, e.g. to move blocks between two tasks.
+    pub fn split(&mut self) -> (Producer<'_, S, N, Q>, Consumer<'_, S, N, Q>) {
+        *self.read.get_mut() = 0;
+        *self.write.get_mut() = 0;
+        *self.xruns.get_mut() = 0;
+        *self.drop_oldest.get_mut() = false;
+        let queue: &Self = self;
+        (Producer { queue }, Consumer { queue })
+    }
+
+    fn len(&self) -> usize {
+        let write = self.write.load(Ordering::Acquire);
+        let read = self.read.load(Ordering::Acquire);
+        Self::distance(write, read)
+    }
+
+    /// Number of blocks between `read` and `write`.
+    const fn distance(write: usize, read: usize) -> usize {
+        (write + 2 * Q - read) % (2 * Q)
+    }
+
+    const fn next(index: usize) -> usize {
+        (index + 1) % (2 * Q)
+    }
+
+    fn slot(&self, index: usize) -> *mut [Frame<S>; N] {
+        (self.blocks.get() as *mut [Frame<S>; N]).wrapping_add(index % Q)
+    }
+}
+
+/// Writing end of a `BlockQueue`.
+pub struct Producer<'a, S, const N: usize, const Q: usize> {
+    queue: &'a BlockQueue<S, N, Q>,
+}
+
+impl<S: Sample, const N: usize, const Q: usize> Producer<'_, S, N, Q> {
+    /// Queues `block` if there is room. Missing frames are filled with silence.
+    pub fn try_write(&mut self, block: &[Frame<S>]) -> bool {
+        let write = self.queue.write.load(Ordering::Relaxed);
+        let read = self.queue.read.load(Ordering::Acquire);
+        if BlockQueue::<S, N, Q>::distance(write, read) == Q {
+            return false;
+        }
+        // SAFETY: the slot is outside `read..write`, so the consumer does not access it.
+        let slot = unsafe { &mut *self.queue.slot(write) };
+        let (copied, rest) = slot.split_at_mut(block.len().min(N));
+        copied.copy_from_slice(&block[..copied.len()]);
+        rest.fill([S::default(); sample::CHANNELS]);
+        self.queue
+            .write
+            .store(BlockQueue::<S, N, Q>::next(write), Ordering::Release);
+        self.queue.readable.wake();
+        true
+    }
+
+    /// Queues `block`, waiting for room.
+    pub async fn write(&mut self, block: &[Frame<S>]) {
+        poll_fn(|cx| {
+            self.queue.writable.register(cx.waker());
+            if self.try_write(block) {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Number of queued blocks.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub const fn capacity(&self) -> usize {
+        Q
+    }
+
+    /// Underruns or overruns counted by the audio side of this queue.
+    pub fn xruns(&self) -> u32 {
+        self.queue.xruns.load(Ordering::Relaxed)
+    }
+}
+
+/// Reading end of a `BlockQueue`.
+pub struct Consumer<'a, S, const N: usize, const Q: usize> {
+    queue: &'a BlockQueue<S, N, Q>,
+}
+
+impl<S: Sample, const N: usize, const Q: usize> Consumer<'_, S, N, Q> {
+    /// Takes the oldest block into `block`, if there is one.
+    pub fn try_read(&mut self, block: &mut [Frame<S>]) -> bool {
+        if self.queue.drop_oldest.load(Ordering::Relaxed) {
+            // `AudioSink::push` may drop the slot being copied, see `OverrunPolicy::DropOldest`
+            self.queue.lock.lock(|| self.read_slot(block))
+        } else {
+            self.read_slot(block)
+        }
+    }
+
+    fn read_slot(&mut self, block: &mut [Frame<S>]) -> bool {
+        let read = self.queue.read.load(Ordering::Relaxed);
+        let write = self.queue.write.load(Ordering::Acquire);
+        if read == write {
+            return false;
+        }
+        // SAFETY: the slot is inside `read..write`, so the producer has finished writing it
+        // and does not touch it until `read` moves on.
+        let slot = unsafe { &*self.queue.slot(read) };
+        for (dst, src) in block.iter_mut().zip(slot.iter()) {
+            *dst = *src;
+        }
+        self.queue
+            .read
+            .store(BlockQueue::<S, N, Q>::next(read), Ordering::Release);
+        self.queue.writable.wake();
+        true
+    }
+
+    /// Takes the oldest block into `block`, waiting for one.
+    pub async fn read(&mut self, block: &mut [Frame<S>]) {
+        poll_fn(|cx| {
+            self.queue.readable.register(cx.waker());
+            if self.try_read(block) {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Number of queued blocks.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub const fn capacity(&self) -> usize {
+        Q
+    }
+
+    /// Underruns or overruns counted by the audio side of this queue.
+    pub fn xruns(&self) -> u32 {
+        self.queue.xruns.load(Ordering::Relaxed)
+    }
+}
+
+/// Audio side of a playback queue, see `BlockQueue::split_source()`.
+pub struct AudioSource<'a, S, const N: usize, const Q: usize> {
+    consumer: Consumer<'a, S, N, Q>,
+    policy: UnderrunPolicy,
+    last: [Frame<S>; N],
+}
+
+impl<S: Sample, const N: usize, const Q: usize> AudioSource<'_, S, N, Q> {
+    /// Fills `output` with the next block, or according to the `UnderrunPolicy` if there is none.
+    /// Frames of `output` beyond `N` are silenced.
+    pub fn pull(&mut self, output: &mut [Frame<S>]) {
+        if !self.consumer.try_read(&mut self.last) {
+            self.consumer.queue.xruns.fetch_add(1, Ordering::Relaxed);
+            if self.policy == UnderrunPolicy::Silence {
+                self.last = [[S::default(); sample::CHANNELS]; N];
+            }
+        }
+        let (played, rest) = output.split_at_mut(output.len().min(N));
+        played.copy_from_slice(&self.last[..played.len()]);
+        rest.fill([S::default(); sample::CHANNELS]);
+    }
+
+    /// Number of queued blocks.
+    pub fn len(&self) -> usize {
+        self.consumer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.consumer.is_empty()
+    }
+
+    pub const fn capacity(&self) -> usize {
+        Q
+    }
+
+    /// Blocks played according to the `UnderrunPolicy` because the queue was empty.
+    pub fn underruns(&self) -> u32 {
+        self.consumer.xruns()
+    }
+}
+
+/// Plays the queue and ignores the input. Blocks longer than `N` are rejected by `prepare()`.
+impl<S: Sample, const N: usize, const Q: usize> AudioProcessor<S> for AudioSource<'_, S, N, Q> {
+    fn prepare(&mut self, _fs: Fs, block_size: usize) -> Result<(), PrepareError> {
+        check_block_size(block_size, N)
+    }
+
+    fn process(&mut self, _input: &[Frame<S>], output: &mut [Frame<S>]) {
+        self.pull(output);
+    }
+}
+
+/// Audio side of a recording queue, see `BlockQueue::split_sink()`.
+pub struct AudioSink<'a, S, const N: usize, const Q: usize> {
+    producer: Producer<'a, S, N, Q>,
+    policy: OverrunPolicy,
+}
+
+impl<S: Sample, const N: usize, const Q: usize> AudioSink<'_, S, N, Q> {
+    /// Queues `input`. If the queue is full, a block is dropped according to the `OverrunPolicy`
+    /// and counted.
+    pub fn push(&mut self, input: &[Frame<S>]) {
+        if self.producer.try_write(input) {
+            return;
+        }
+        let queue = self.producer.queue;
+        queue.xruns.fetch_add(1, Ordering::Relaxed);
+        if self.policy == OverrunPolicy::DropOldest {
+            queue.lock.lock(|| {
+                // the consumer only copies inside the lock, so the oldest slot is not being read
+                let read = queue.read.load(Ordering::Relaxed);
+                if BlockQueue::<S, N, Q>::distance(queue.write.load(Ordering::Relaxed), read) == Q {
+                    queue
+                        .read
+                        .store(BlockQueue::<S, N, Q>::next(read), Ordering::Release);
+                }
+            });
+            self.producer.try_write(input);
+        }
+    }
+
+    /// Number of queued blocks.
+    pub fn len(&self) -> usize {
+        self.producer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.producer.is_empty()
+    }
+
+    pub const fn capacity(&self) -> usize {
+        Q
+    }
+
+    /// Blocks dropped because the queue was full, new or old according to the `OverrunPolicy`.
+    pub fn overruns(&self) -> u32 {
+        self.producer.xruns()
+    }
+}
+
+/// Records the input and passes it through to the output. Blocks longer than `N` are rejected by `prepare()`.
+impl<S: Sample, const N: usize, const Q: usize> AudioProcessor<S> for AudioSink<'_, S, N, Q> {
+    fn prepare(&mut self, _fs: Fs, block_size: usize) -> Result<(), PrepareError> {
+        check_block_size(block_size, N)
+    }
+
+    fn process(&mut self, input: &[Frame<S>], output: &mut [Frame<S>]) {
+        self.push(input);
+        output.copy_from_slice(input);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(value: i32) -> [Frame<i32>; 2] {
+        [[value, -value]; 2]
+    }
+
+    #[test]
+    fn blocks_come_out_in_order_across_the_index_wrap() {
+        // 3 does not divide the index range, so this checks the wrap at `2 * Q`
+        let mut queue = BlockQueue::<i32, 2, 3>::new();
+        let (mut producer, mut consumer) = queue.split();
+        let mut out = block(0);
+        for round in 0..10 {
+            for i in 0..3 {
+                assert!(producer.try_write(&block(round * 3 + i)));
+            }
+            assert!(!producer.try_write(&block(-1)));
+            assert_eq!(producer.len(), 3);
+            for i in 0..3 {
+                assert!(consumer.try_read(&mut out));
+                assert_eq!(out, block(round * 3 + i));
+            }
+            assert!(!consumer.try_read(&mut out));
+            assert!(consumer.is_empty());
+        }
+    }
+
+    #[test]
+    fn interleaved_reads_and_writes_keep_the_length() {
+        let mut queue = BlockQueue::<i32, 2, 3>::new();
+        let (mut producer, mut consumer) = queue.split();
+        let mut out = block(0);
+        assert!(producer.try_write(&block(0)));
+        for i in 1..20 {
+            assert!(producer.try_write(&block(i)));
+            assert_eq!(consumer.len(), 2);
+            assert!(consumer.try_read(&mut out));
+            assert_eq!(out, block(i - 1));
+        }
+    }
+
+    #[test]
+    fn sink_drops_the_newest_block_by_default() {
+        let mut queue = BlockQueue::<i32, 2, 2>::new();
+        let (mut sink, mut consumer) = queue.split_sink(OverrunPolicy::default());
+        for i in 1..=4 {
+            sink.push(&block(i));
+        }
+        assert_eq!(sink.overruns(), 2);
+        let mut out = block(0);
+        for i in 1..=2 {
+            assert!(consumer.try_read(&mut out));
+            assert_eq!(out, block(i));
+        }
+        assert!(!consumer.try_read(&mut out));
+    }
+
+    #[test]
+    fn sink_can_drop_the_oldest_block() {
+        let mut queue = BlockQueue::<i32, 2, 2>::new();
+        let (mut sink, mut consumer) = queue.split_sink(OverrunPolicy::DropOldest);
+        let mut out = block(0);
+        for i in 1..=5 {
+            sink.push(&block(i));
+        }
+        assert_eq!(sink.overruns(), 3);
+        assert_eq!(sink.len(), 2);
+        for i in 4..=5 {
+            assert!(consumer.try_read(&mut out));
+            assert_eq!(out, block(i));
+        }
+        assert!(!consumer.try_read(&mut out));
+
+        // reads and drops keep taking turns across the index wrap
+        for i in 6..20 {
+            sink.push(&block(i));
+            sink.push(&block(i * 100));
+            sink.push(&block(i * 1000));
+            assert!(consumer.try_read(&mut out));
+            assert_eq!(out, block(i * 100));
+            assert!(consumer.try_read(&mut out));
+            assert_eq!(out, block(i * 1000));
+        }
+    }
+
+    #[test]
+    fn split_resets_the_overrun_policy() {
+        let mut queue = BlockQueue::<i32, 2, 1>::new();
+        queue.split_sink(OverrunPolicy::DropOldest);
+        let (mut sink, mut consumer) = queue.split_sink(OverrunPolicy::DropNewest);
+        sink.push(&block(1));
+        sink.push(&block(2));
+        let mut out = block(0);
+        assert!(consumer.try_read(&mut out));
+        assert_eq!(out, block(1));
+    }
+
+    #[test]
+    fn source_silences_output_beyond_the_block_length() {
+        let mut queue = BlockQueue::<i32, 2, 1>::new();
+        let (mut producer, mut source) = queue.split_source(UnderrunPolicy::Repeat);
+        assert!(producer.try_write(&block(3)));
+        let mut out = [[9, 9]; 3];
+        source.pull(&mut out);
+        assert_eq!(out, [[3, -3], [3, -3], [0, 0]]);
+
+        // an underrun repeats the last block, still without the extra frame
+        let mut out = [[9, 9]; 3];
+        source.pull(&mut out);
+        assert_eq!(out, [[3, -3], [3, -3], [0, 0]]);
+        assert_eq!(source.underruns(), 1);
+
+        let mut out = [[9, 9]; 1];
+        source.pull(&mut out);
+        assert_eq!(out, [[3, -3]]);
+    }
+
+    #[test]
+    fn source_plays_silence_on_underrun() {
+        let mut queue = BlockQueue::<i32, 2, 1>::new();
+        let (mut producer, mut source) = queue.split_source(UnderrunPolicy::Silence);
+        assert!(producer.try_write(&block(3)));
+        let mut out = block(0);
+        source.pull(&mut out);
+        assert_eq!(out, block(3));
+        source.pull(&mut out);
+        assert_eq!(out, block(0));
+        assert_eq!(source.underruns(), 1);
+    }
+
+    #[test]
+    fn short_blocks_are_padded_with_silence() {
+        let mut queue = BlockQueue::<i32, 2, 1>::new();
+        let (mut producer, mut consumer) = queue.split();
+        assert!(producer.try_write(&[[7, 8]]));
+        let mut out = block(1);
+        assert!(consumer.try_read(&mut out));
+        assert_eq!(out, [[7, 8], [0, 0]]);
+    }
+}
@@ -0,0 +1,87 @@
+//! Support for processing directly in the DMA buffers, see `Interface::start_callback_in_place`.
+//!
+//! The embassy ring buffers copy every block, so the in-place mode does not use them.
+//! Instead it follows the DMA streams: when the receive stream enters one half of its buffer,
+//! the other half holds a new block of input. The transmit stream runs ahead of it by the prefill
+//! and the SAI FIFO, so the half of the transmit buffer the CPU may write is read from its own
+//! stream. SAI sub-blocks A and B share their clocks, so the distance between them stays fixed.
+use core::future::poll_fn;
+use core::ptr::NonNull;
+use core::task::Poll;
+
+use embassy_stm32::{self as hal, interrupt::typelevel::Interrupt};
+use embassy_sync::waitqueue::AtomicWaker;
+
+static DMA_WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Wakes `wait_for_half` on every half-transfer and transfer-complete interrupt of the audio DMA streams.
+/// Bound next to the embassy DMA handler in `AudioIrqs`, which clears the flags.
+pub struct HalfTransferHandler;
+
+impl<I: Interrupt> hal::interrupt::typelevel::Handler<I> for HalfTransferHandler {
+    unsafe fn on_interrupt() {
+        DMA_WAKER.wake();
+    }
+}
+
+/// Keeps the DMA buffers on separate cache lines, so cache maintenance on them
+/// never touches neighbouring data.
+#[repr(C, align(32))]
+pub(crate) struct CacheAligned<T>(pub T);
+
+/// Where the SAI1 DMA rings are. The codec's ring buffers own them; only the `InPlace` state
+/// goes through these pointers, in which the ring buffers are not used.
+#[derive(Clone, Copy)]
+pub(crate) struct DmaBufferPtrs {
+    tx: NonNull<u32>,
+    rx: NonNull<u32>,
+    length: usize,
+}
+
+// SAFETY: the pointers are only dereferenced by `Interface`, which owns the ring buffers.
+unsafe impl Send for DmaBufferPtrs {}
+
+impl DmaBufferPtrs {
+    pub(crate) fn new(tx_buffer: &mut [u32], rx_buffer: &mut [u32]) -> Self {
+        Self {
+            tx: NonNull::from(&mut tx_buffer[0]),
+            rx: NonNull::from(&mut rx_buffer[0]),
+            length: tx_buffer.len(),
+        }
+    }
+
+    /// The transmit and receive rings.
+    ///
+    /// # Safety
+    /// The ring buffers must not be used while the returned slices live.
+    pub(crate) unsafe fn get(&mut self) -> (&mut [u32], &mut [u32]) {
+        // SAFETY: see `new`, and the caller keeps the ring buffers away.
+        unsafe {
+            (
+                core::slice::from_raw_parts_mut(self.tx.as_ptr(), self.length),
+                core::slice::from_raw_parts_mut(self.rx.as_ptr(), self.length),
+            )
+        }
+    }
+}
+
+/// The half of the buffer DMA `stream` is working on, `0` or `1`.
+pub(crate) fn dma_half(stream: usize, buffer_length: usize) -> usize {
+    // NDTR counts the remaining words down to 1 and reloads at the end of the buffer.
+    let remaining = hal::pac::DMA1.st(stream).ndtr().read().ndt() as usize;
+    if remaining > buffer_length / 2 { 0 } else { 1 }
+}
+
+/// Waits until DMA `stream` has left half `current` and returns the half it is in now.
+pub(crate) async fn wait_for_half(stream: usize, buffer_length: usize, current: usize) -> usize {
+    poll_fn(|cx| {
+        DMA_WAKER.register(cx.waker());
+        let half = dma_half(stream, buffer_length);
+        if half != current {
+            Poll::Ready(half)
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
+}
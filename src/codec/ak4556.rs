@@ -7,7 +7,10 @@ use hal::peripherals::*;
 
 use embassy_time::Timer;
 
-use crate::audio::{AudioConfig, AudioIrqs, AudioPeripherals, HALF_DMA_BUFFER_LENGTH};
+use super::{AudioCodec, CodecError, PowerState, SerialFormat};
+use crate::audio::{
+    AudioConfig, AudioIrqs, AudioPeripherals, Capabilities, ClockRatio, Error, Fs, SILENCE,
+};
 use defmt::info;
 use hal::sai::FifoThreshold;
 use hal::sai::FrameSyncOffset;
@@ -21,28 +24,75 @@ pub struct Codec<'a> {
     sai_rx: sai::Sai<'a, peripherals::SAI1, u32>,
     pub sai_tx_config: sai::Config,
     pub sai_rx_config: sai::Config,
+    half_dma_buffer_length: usize,
+    power: PowerState,
 }
 
 impl<'a> Codec<'a> {
-    pub async fn new(
-        p: AudioPeripherals<'a>,
+    pub fn release(self) -> (sai::Sai<'a, SAI1, u32>, sai::Sai<'a, SAI1, u32>, Output<'a>) {
+        (self.sai_tx, self.sai_rx, self.reset)
+    }
+}
+
+impl<'a> AudioCodec<'a> for Codec<'a> {
+    type Pins = Pins<'a>;
+
+    /// In normal and double speed mode the AK4556 accepts 256 x fs up to 96kHz
+    /// and 512 x fs up to 48kHz.
+    const CAPABILITIES: Capabilities = Capabilities {
+        modes: &[
+            (
+                ClockRatio::Ratio256,
+                &[
+                    Fs::Fs8000,
+                    Fs::Fs16000,
+                    Fs::Fs22050,
+                    Fs::Fs24000,
+                    Fs::Fs32000,
+                    Fs::Fs44100,
+                    Fs::Fs48000,
+                    Fs::Fs88200,
+                    Fs::Fs96000,
+                ],
+            ),
+            (
+                ClockRatio::Ratio512,
+                &[
+                    Fs::Fs8000,
+                    Fs::Fs16000,
+                    Fs::Fs22050,
+                    Fs::Fs24000,
+                    Fs::Fs32000,
+                    Fs::Fs44100,
+                    Fs::Fs48000,
+                ],
+            ),
+        ],
+    };
+
+    const RX_DMA_STREAM: usize = 1;
+
+    async fn new(
+        p: AudioPeripherals<'a, Pins<'a>>,
         audio_config: AudioConfig,
         tx_buffer: &'a mut [u32],
         rx_buffer: &'a mut [u32],
-    ) -> Self {
+    ) -> Result<Self, CodecError> {
         info!("set up AK4556");
 
         let reset = Output::new(p.codec_pins.RESET, Level::High, Speed::Low);
 
         let (sub_block_tx, sub_block_rx) = hal::sai::split_subblocks(p.sai1);
 
+        let half_dma_buffer_length = tx_buffer.len() / 2;
+
         info!("set up sai");
         let mut sai_tx_config = sai::Config::default();
         sai_tx_config.mode = Mode::Master;
         sai_tx_config.tx_rx = TxRx::Transmitter;
         sai_tx_config.sync_output = true;
         sai_tx_config.clock_strobe = ClockStrobe::Falling;
-        sai_tx_config.master_clock_divider = audio_config.fs.into_clock_divider();
+        sai_tx_config.master_clock_divider = audio_config.master_clock_divider();
         sai_tx_config.stereo_mono = StereoMono::Stereo;
         sai_tx_config.data_size = DataSize::Data24;
         sai_tx_config.bit_order = BitOrder::MsbFirst;
@@ -80,16 +130,18 @@ impl<'a> Codec<'a> {
             sai_rx_config,
         );
 
-        Self {
+        Ok(Self {
             reset,
             sai_tx,
             sai_rx,
             sai_tx_config,
             sai_rx_config,
-        }
+            half_dma_buffer_length,
+            power: PowerState::Standby,
+        })
     }
 
-    pub async fn start(&mut self) -> Result<(), sai::Error> {
+    async fn start(&mut self) -> Result<(), Error> {
         info!("start AK4556");
 
         self.reset.set_high();
@@ -101,18 +153,80 @@ impl<'a> Codec<'a> {
         Timer::after_millis(10).await;
 
         info!("start SAI");
-        let write_buf = [0; HALF_DMA_BUFFER_LENGTH];
-        self.sai_tx.write(&write_buf).await?;
-        self.sai_rx.start()
+        let write_buf = &SILENCE[..self.half_dma_buffer_length];
+        self.sai_tx.write(write_buf).await?;
+        self.power = PowerState::Active;
+        Ok(self.sai_rx.start()?)
+    }
+
+    /// The AK4556 derives its sampling rate from MCLK/LRCK, so there is nothing to reconfigure.
+    async fn set_sample_rate(&mut self, _fs: Fs) -> Result<(), CodecError> {
+        Ok(())
+    }
+
+    /// Powers the AK4556 down through its PDN pin. Called with the SAI paused.
+    async fn stop(&mut self) -> Result<(), CodecError> {
+        info!("power down AK4556");
+        self.reset.set_low();
+        self.power = PowerState::Stopped;
+        Ok(())
+    }
+
+    /// Powers the AK4556 up again after `stop()`. Called before the SAI resumes.
+    async fn resume(&mut self) -> Result<(), CodecError> {
+        info!("power up AK4556");
+        self.reset.set_high();
+        Timer::after_millis(10).await;
+        self.power = PowerState::Active;
+        Ok(())
     }
 
-    pub async fn read(&mut self, read_buf: &mut [u32]) -> Result<(), sai::Error> {
+    /// Same as `stop()`: in power-down the AK4556 keeps nothing worth restoring.
+    async fn suspend(&mut self) -> Result<(), CodecError> {
+        info!("suspend AK4556");
+        self.reset.set_low();
+        self.power = PowerState::Suspended;
+        Ok(())
+    }
+
+    /// Releases PDN once MCLK runs again. The AK4556 needs MCLK to come out of power-down.
+    async fn wake(&mut self) -> Result<(), CodecError> {
+        info!("wake AK4556");
+        self.reset.set_high();
+        Timer::after_millis(10).await;
+        self.power = PowerState::Active;
+        Ok(())
+    }
+
+    fn power_state(&self) -> PowerState {
+        self.power
+    }
+
+    /// Fixed to 24-bit left-justified by the DIF pin on the Seed.
+    async fn serial_format(&mut self) -> Result<SerialFormat, CodecError> {
+        Ok(SerialFormat::LeftJustified24)
+    }
+
+    async fn read(&mut self, read_buf: &mut [u32]) -> Result<(), sai::Error> {
         self.sai_rx.read(read_buf).await
     }
 
-    pub async fn write(&mut self, write_buf: &[u32]) -> Result<(), sai::Error> {
+    async fn write(&mut self, write_buf: &[u32]) -> Result<(), sai::Error> {
         self.sai_tx.write(write_buf).await
     }
+
+    fn sai_tx_config(&self) -> &sai::Config {
+        &self.sai_tx_config
+    }
+
+    fn sai_rx_config(&self) -> &sai::Config {
+        &self.sai_rx_config
+    }
+
+    fn set_master_clock_divider(&mut self, divider: sai::MasterClockDivider) {
+        self.sai_tx_config.master_clock_divider = divider;
+        self.sai_rx_config.master_clock_divider = divider;
+    }
 }
 
 #[allow(non_snake_case)]
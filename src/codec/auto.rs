@@ -0,0 +1,342 @@
+//! Selects the codec driver at boot, so one firmware runs on every Daisy Seed revision.
+//!
+//! Like libDaisy, the revision is read from the strap pins first: PD3 is tied low on Seed 1.1
+//! (WM8731) and PD4 on Seed 1.2 (PCM3060). If neither is low, the WM8731 is probed on I2C,
+//! and the AK4556 of the original Seed is assumed when nothing answers.
+use embassy_stm32::{
+    self as hal, Peri,
+    gpio::{Input, Pull},
+    sai::{self, MasterClockDivider},
+};
+use hal::peripherals::*;
+
+use defmt::info;
+use embassy_time::Timer;
+
+use super::{
+    AudioCodec, CodecError, PowerState, SerialFormat, ak4556,
+    i2c_bus::{CodecI2c, CodecI2cIrqs},
+    pcm3060, wm8731,
+};
+use crate::audio::{AudioConfig, AudioPeripherals, Capabilities, ClockRatio, Error, Fs};
+
+/// Daisy Seed hardware revision, named after the matching board feature.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Revision {
+    /// AK4556, feature `seed`
+    Seed,
+    /// WM8731, feature `seed_1_1`
+    Seed1_1,
+    /// PCM3060 configured by hardware, feature `seed_1_2`
+    Seed1_2,
+}
+
+enum Inner<'a> {
+    Ak4556(ak4556::Codec<'a>),
+    Wm8731(wm8731::Codec<'a>),
+    Pcm3060(pcm3060::Codec<'a>),
+}
+
+/// The codec driver of the detected revision.
+pub struct Codec<'a> {
+    inner: Inner<'a>,
+    revision: Revision,
+}
+
+impl<'a> Codec<'a> {
+    /// The revision detected in `new()`.
+    pub fn revision(&self) -> Revision {
+        self.revision
+    }
+
+    /// The WM8731 driver on Seed 1.1, for its runtime controls.
+    pub fn wm8731(&mut self) -> Option<&mut wm8731::Codec<'a>> {
+        match &mut self.inner {
+            Inner::Wm8731(codec) => Some(codec),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> AudioCodec<'a> for Codec<'a> {
+    type Pins = Pins<'a>;
+
+    /// The rates every revision supports, because they are validated before detection runs.
+    /// The WM8731 driver only runs at 256 x fs, so 512 x fs is not available.
+    const CAPABILITIES: Capabilities = Capabilities {
+        modes: &[(
+            ClockRatio::Ratio256,
+            &[Fs::Fs44100, Fs::Fs48000, Fs::Fs88200, Fs::Fs96000],
+        )],
+    };
+
+    /// The same on every revision.
+    const RX_DMA_STREAM: usize = 1;
+
+    async fn new(
+        p: AudioPeripherals<'a, Pins<'a>>,
+        audio_config: AudioConfig,
+        tx_buffer: &'a mut [u32],
+        rx_buffer: &'a mut [u32],
+    ) -> Result<Self, CodecError> {
+        let AudioPeripherals {
+            codec_pins: mut pins,
+            sai1,
+            mut i2c2,
+            dma1_ch0,
+            dma1_ch1,
+            mut dma1_ch4,
+            mut dma1_ch5,
+            i2c_irqs,
+        } = p;
+        let revision = detect(&mut pins, &mut i2c2, &mut dma1_ch4, &mut dma1_ch5, i2c_irqs).await;
+        info!("detected Daisy {}", revision);
+
+        let inner = match revision {
+            Revision::Seed => {
+                let p = AudioPeripherals {
+                    codec_pins: ak4556::Pins {
+                        MCLK_A: pins.MCLK_A,
+                        SCK_A: pins.SCK_A,
+                        FS_A: pins.FS_A,
+                        SD_A: pins.SD_A,
+                        SD_B: pins.SD_B,
+                        RESET: pins.SDA,
+                    },
+                    sai1,
+                    i2c2,
+                    dma1_ch0,
+                    dma1_ch1,
+                    dma1_ch4,
+                    dma1_ch5,
+                    i2c_irqs,
+                };
+                Inner::Ak4556(ak4556::Codec::new(p, audio_config, tx_buffer, rx_buffer).await?)
+            }
+            Revision::Seed1_1 => {
+                let p = AudioPeripherals {
+                    codec_pins: wm8731::Pins {
+                        SCL: pins.SCL,
+                        SDA: pins.SDA,
+                        MCLK_A: pins.MCLK_A,
+                        SCK_A: pins.SCK_A,
+                        FS_A: pins.FS_A,
+                        SD_A: pins.SD_A,
+                        SD_B: pins.SD_B,
+                    },
+                    sai1,
+                    i2c2,
+                    dma1_ch0,
+                    dma1_ch1,
+                    dma1_ch4,
+                    dma1_ch5,
+                    i2c_irqs,
+                };
+                Inner::Wm8731(wm8731::Codec::new(p, audio_config, tx_buffer, rx_buffer).await?)
+            }
+            Revision::Seed1_2 => {
+                let p = AudioPeripherals {
+                    codec_pins: pcm3060::Pins {
+                        MCLK_A: pins.MCLK_A,
+                        SCK_A: pins.SCK_A,
+                        FS_A: pins.FS_A,
+                        SD_A: pins.SD_A,
+                        SD_B: pins.SD_B,
+                    },
+                    sai1,
+                    i2c2,
+                    dma1_ch0,
+                    dma1_ch1,
+                    dma1_ch4,
+                    dma1_ch5,
+                    i2c_irqs,
+                };
+                Inner::Pcm3060(pcm3060::Codec::new(p, audio_config, tx_buffer, rx_buffer).await?)
+            }
+        };
+
+        Ok(Self { inner, revision })
+    }
+
+    async fn start(&mut self) -> Result<(), Error> {
+        match &mut self.inner {
+            Inner::Ak4556(codec) => codec.start().await,
+            Inner::Wm8731(codec) => codec.start().await,
+            Inner::Pcm3060(codec) => codec.start().await,
+        }
+    }
+
+    async fn stop(&mut self) -> Result<(), CodecError> {
+        match &mut self.inner {
+            Inner::Ak4556(codec) => codec.stop().await,
+            Inner::Wm8731(codec) => codec.stop().await,
+            Inner::Pcm3060(codec) => codec.stop().await,
+        }
+    }
+
+    async fn resume(&mut self) -> Result<(), CodecError> {
+        match &mut self.inner {
+            Inner::Ak4556(codec) => codec.resume().await,
+            Inner::Wm8731(codec) => codec.resume().await,
+            Inner::Pcm3060(codec) => codec.resume().await,
+        }
+    }
+
+    async fn suspend(&mut self) -> Result<(), CodecError> {
+        match &mut self.inner {
+            Inner::Ak4556(codec) => codec.suspend().await,
+            Inner::Wm8731(codec) => codec.suspend().await,
+            Inner::Pcm3060(codec) => codec.suspend().await,
+        }
+    }
+
+    async fn wake(&mut self) -> Result<(), CodecError> {
+        match &mut self.inner {
+            Inner::Ak4556(codec) => codec.wake().await,
+            Inner::Wm8731(codec) => codec.wake().await,
+            Inner::Pcm3060(codec) => codec.wake().await,
+        }
+    }
+
+    async fn set_sample_rate(&mut self, fs: Fs) -> Result<(), CodecError> {
+        match &mut self.inner {
+            Inner::Ak4556(codec) => codec.set_sample_rate(fs).await,
+            Inner::Wm8731(codec) => codec.set_sample_rate(fs).await,
+            Inner::Pcm3060(codec) => codec.set_sample_rate(fs).await,
+        }
+    }
+
+    fn can_mute(&self) -> bool {
+        match &self.inner {
+            Inner::Ak4556(codec) => codec.can_mute(),
+            Inner::Wm8731(codec) => codec.can_mute(),
+            Inner::Pcm3060(codec) => codec.can_mute(),
+        }
+    }
+
+    async fn set_mute(&mut self, mute: bool) -> Result<(), CodecError> {
+        match &mut self.inner {
+            Inner::Ak4556(codec) => codec.set_mute(mute).await,
+            Inner::Wm8731(codec) => codec.set_mute(mute).await,
+            Inner::Pcm3060(codec) => codec.set_mute(mute).await,
+        }
+    }
+
+    fn power_state(&self) -> PowerState {
+        match &self.inner {
+            Inner::Ak4556(codec) => codec.power_state(),
+            Inner::Wm8731(codec) => codec.power_state(),
+            Inner::Pcm3060(codec) => codec.power_state(),
+        }
+    }
+
+    async fn serial_format(&mut self) -> Result<SerialFormat, CodecError> {
+        match &mut self.inner {
+            Inner::Ak4556(codec) => codec.serial_format().await,
+            Inner::Wm8731(codec) => codec.serial_format().await,
+            Inner::Pcm3060(codec) => codec.serial_format().await,
+        }
+    }
+
+    async fn log_registers(&mut self) -> Result<(), CodecError> {
+        match &mut self.inner {
+            Inner::Ak4556(codec) => codec.log_registers().await,
+            Inner::Wm8731(codec) => codec.log_registers().await,
+            Inner::Pcm3060(codec) => codec.log_registers().await,
+        }
+    }
+
+    async fn read(&mut self, read_buf: &mut [u32]) -> Result<(), sai::Error> {
+        match &mut self.inner {
+            Inner::Ak4556(codec) => codec.read(read_buf).await,
+            Inner::Wm8731(codec) => codec.read(read_buf).await,
+            Inner::Pcm3060(codec) => codec.read(read_buf).await,
+        }
+    }
+
+    async fn write(&mut self, write_buf: &[u32]) -> Result<(), sai::Error> {
+        match &mut self.inner {
+            Inner::Ak4556(codec) => codec.write(write_buf).await,
+            Inner::Wm8731(codec) => codec.write(write_buf).await,
+            Inner::Pcm3060(codec) => codec.write(write_buf).await,
+        }
+    }
+
+    fn sai_tx_config(&self) -> &sai::Config {
+        match &self.inner {
+            Inner::Ak4556(codec) => codec.sai_tx_config(),
+            Inner::Wm8731(codec) => codec.sai_tx_config(),
+            Inner::Pcm3060(codec) => codec.sai_tx_config(),
+        }
+    }
+
+    fn sai_rx_config(&self) -> &sai::Config {
+        match &self.inner {
+            Inner::Ak4556(codec) => codec.sai_rx_config(),
+            Inner::Wm8731(codec) => codec.sai_rx_config(),
+            Inner::Pcm3060(codec) => codec.sai_rx_config(),
+        }
+    }
+
+    fn set_master_clock_divider(&mut self, divider: MasterClockDivider) {
+        match &mut self.inner {
+            Inner::Ak4556(codec) => codec.set_master_clock_divider(divider),
+            Inner::Wm8731(codec) => codec.set_master_clock_divider(divider),
+            Inner::Pcm3060(codec) => codec.set_master_clock_divider(divider),
+        }
+    }
+}
+
+/// Reads the strap pins, then probes the WM8731 if they are inconclusive.
+async fn detect(
+    pins: &mut Pins<'_>,
+    i2c2: &mut Peri<'_, I2C2>,
+    dma1_ch4: &mut Peri<'_, DMA1_CH4>,
+    dma1_ch5: &mut Peri<'_, DMA1_CH5>,
+    i2c_irqs: CodecI2cIrqs,
+) -> Revision {
+    {
+        let detect_1_1 = Input::new(pins.DETECT_1_1.reborrow(), Pull::Up);
+        let detect_1_2 = Input::new(pins.DETECT_1_2.reborrow(), Pull::Up);
+        Timer::after_micros(10).await; // let the pull-ups settle
+        if detect_1_1.is_low() {
+            return Revision::Seed1_1;
+        }
+        if detect_1_2.is_low() {
+            return Revision::Seed1_2;
+        }
+    }
+
+    info!("no revision strap, probing WM8731");
+    let mut i2c = CodecI2c::new(
+        i2c2.reborrow(),
+        pins.SCL.reborrow(),
+        pins.SDA.reborrow(),
+        dma1_ch4.reborrow(),
+        dma1_ch5.reborrow(),
+        i2c_irqs,
+        wm8731::I2C_ADDRESS,
+    );
+    // Writes the reset register, which the WM8731 driver writes first anyway. Not retried, as
+    // a NAK is the expected answer on the original Seed, where SDA is the AK4556 reset line
+    // (`start()` pulses it before use).
+    if i2c.try_write(&[0x0f << 1, 0]).await.is_ok() {
+        Revision::Seed1_1
+    } else {
+        Revision::Seed
+    }
+}
+
+/// The pins of all Seed codecs, plus the revision straps.
+#[allow(non_snake_case)]
+pub struct Pins<'a> {
+    pub SCL: Peri<'a, PH4>,        // I2C2 SCL (WM8731)
+    pub SDA: Peri<'a, PB11>,       // I2C2 SDA (WM8731), RESET (AK4556)
+    pub MCLK_A: Peri<'a, PE2>,     // SAI1 MCLK_A
+    pub SCK_A: Peri<'a, PE5>,      // SAI1 SCK_A
+    pub FS_A: Peri<'a, PE4>,       // SAI1 FS_A
+    pub SD_A: Peri<'a, PE6>,       // SAI1 SD_A
+    pub SD_B: Peri<'a, PE3>,       // SAI1 SD_B
+    pub DETECT_1_1: Peri<'a, PD3>, // low on Seed 1.1
+    pub DETECT_1_2: Peri<'a, PD4>, // low on Seed 1.2
+}
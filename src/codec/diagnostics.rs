@@ -0,0 +1,85 @@
+//! Register dumps and a format self-test, for finding out why a board outputs silence.
+use embassy_stm32::sai::{self, BitOrder, DataSize, FrameSyncOffset};
+
+use super::CodecError;
+
+/// Serial audio format of the codec, or of the SAI as derived by `sai_format()`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum SerialFormat {
+    /// MSB-justified, 24-bit. What every Daisy codec is set up for.
+    LeftJustified24,
+    I2s24,
+    RightJustified24,
+    RightJustified16,
+    /// Any other format or word length.
+    Other,
+}
+
+/// Why `AudioCodec::self_test()` failed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum SelfTestError {
+    /// The codec registers could not be read back.
+    Codec(CodecError),
+    /// The codec and an SAI sub-block disagree on the serial format.
+    FormatMismatch {
+        codec: SerialFormat,
+        sai: SerialFormat,
+        tx_rx: TxRx,
+    },
+}
+
+/// The SAI sub-block a `SelfTestError::FormatMismatch` was found on.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum TxRx {
+    Transmitter,
+    Receiver,
+}
+
+impl From<CodecError> for SelfTestError {
+    fn from(e: CodecError) -> Self {
+        SelfTestError::Codec(e)
+    }
+}
+
+/// Contents of `N` consecutive codec registers, starting at `first_address`.
+/// Formats as one `address: value` line per register.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RegisterDump<T, const N: usize> {
+    pub first_address: u8,
+    pub values: [T; N],
+}
+
+impl<T: defmt::Format, const N: usize> defmt::Format for RegisterDump<T, N> {
+    fn format(&self, f: defmt::Formatter) {
+        for (address, value) in (self.first_address..).zip(&self.values) {
+            defmt::write!(f, "\n  R{=u8:#04x}: {:#x}", address, value);
+        }
+    }
+}
+
+/// The serial format an SAI sub-block is set up for, in the 64-bit stereo frames all Daisy codecs use.
+pub fn sai_format(config: &sai::Config) -> SerialFormat {
+    if config.frame_length != 64 || !matches!(config.bit_order, BitOrder::MsbFirst) {
+        return SerialFormat::Other;
+    }
+    match (config.data_size, config.frame_sync_offset) {
+        (DataSize::Data24, FrameSyncOffset::OnFirstBit) => SerialFormat::LeftJustified24,
+        (DataSize::Data24, FrameSyncOffset::BeforeFirstBit) => SerialFormat::I2s24,
+        _ => SerialFormat::Other,
+    }
+}
+
+/// Compares the format the codec reports with both SAI sub-blocks.
+pub(crate) fn check_format(
+    codec: SerialFormat,
+    tx_config: &sai::Config,
+    rx_config: &sai::Config,
+) -> Result<(), SelfTestError> {
+    for (config, tx_rx) in [(tx_config, TxRx::Transmitter), (rx_config, TxRx::Receiver)] {
+        let sai = sai_format(config);
+        if sai != codec || codec == SerialFormat::Other {
+            return Err(SelfTestError::FormatMismatch { codec, sai, tx_rx });
+        }
+    }
+    Ok(())
+}
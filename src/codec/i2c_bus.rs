@@ -0,0 +1,148 @@
+//! Async I2C access to the codec registers, shared by the drivers that configure their codec over I2C.
+//!
+//! Transfers run on DMA1 streams 4 and 5, so they do not block the executor. Every transfer is
+//! bounded by `I2C_TIMEOUT` and retried up to `I2C_ATTEMPTS` times, which rides out the occasional
+//! NAK on a loose cable (e.g. a Patch SM on a long ribbon cable).
+use embassy_stm32::{
+    self as hal, Peri, dma,
+    i2c::{self, SclPin, SdaPin},
+    interrupt::typelevel::{self as irq, Binding},
+    time::Hertz,
+};
+use hal::peripherals::*;
+
+use defmt::warn;
+use embassy_time::{Duration, Timer, with_timeout};
+
+use super::CodecError;
+
+/// Proof that the interrupts of the codec I2C bus are bound, for `AudioPeripherals::i2c_irqs`.
+///
+/// `audio_peripherals!` and `new_daisy_board!` bind them in your crate. If your crate binds any of
+/// them itself, pass your `Irqs` to `new_daisy_board!(p, irqs)` instead:
+/// ```rust
+/// bind_interrupts!(struct Irqs {
+///     DMA1_STREAM4 => dma::InterruptHandler<peripherals::DMA1_CH4>;
+///     DMA1_STREAM5 => dma::InterruptHandler<peripherals::DMA1_CH5>;
+///     I2C2_EV => i2c::EventInterruptHandler<peripherals::I2C2>;
+///     I2C2_ER => i2c::ErrorInterruptHandler<peripherals::I2C2>;
+///     // ... your own interrupts ...
+/// });
+/// let board = new_daisy_board!(p, Irqs);
+/// ```
+#[derive(Clone, Copy)]
+pub struct CodecI2cIrqs {
+    _private: (),
+}
+
+impl CodecI2cIrqs {
+    pub fn new<I>(_irqs: I) -> Self
+    where
+        I: Binding<irq::DMA1_STREAM4, dma::InterruptHandler<DMA1_CH4>>
+            + Binding<irq::DMA1_STREAM5, dma::InterruptHandler<DMA1_CH5>>
+            + Binding<irq::I2C2_EV, i2c::EventInterruptHandler<I2C2>>
+            + Binding<irq::I2C2_ER, i2c::ErrorInterruptHandler<I2C2>>,
+    {
+        Self { _private: () }
+    }
+}
+
+/// Stands in for the `Irqs` given to `CodecI2cIrqs::new`, which are not kept.
+#[derive(Clone, Copy)]
+struct Bound;
+
+// SAFETY: `Bound` is only used with a `CodecI2cIrqs`, which can only be made from these bindings.
+unsafe impl Binding<irq::DMA1_STREAM4, dma::InterruptHandler<DMA1_CH4>> for Bound {}
+unsafe impl Binding<irq::DMA1_STREAM5, dma::InterruptHandler<DMA1_CH5>> for Bound {}
+unsafe impl Binding<irq::I2C2_EV, i2c::EventInterruptHandler<I2C2>> for Bound {}
+unsafe impl Binding<irq::I2C2_ER, i2c::ErrorInterruptHandler<I2C2>> for Bound {}
+
+const I2C_FS: Hertz = Hertz(100_000);
+
+/// Transfers tried before an error is returned.
+pub(crate) const I2C_ATTEMPTS: u32 = 3;
+
+/// A 100kHz transfer of a few bytes takes well under a millisecond.
+const I2C_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// Delay before the first retry, doubled for every further one.
+const RETRY_DELAY: Duration = Duration::from_millis(1);
+
+/// The I2C2 bus with one codec on it.
+pub(crate) struct CodecI2c<'a> {
+    i2c: i2c::I2c<'a, hal::mode::Async, i2c::Master>,
+    address: u8,
+}
+
+impl<'a> CodecI2c<'a> {
+    pub fn new(
+        i2c2: Peri<'a, I2C2>,
+        scl: Peri<'a, impl SclPin<I2C2>>,
+        sda: Peri<'a, impl SdaPin<I2C2>>,
+        tx_dma: Peri<'a, DMA1_CH4>,
+        rx_dma: Peri<'a, DMA1_CH5>,
+        _irqs: CodecI2cIrqs,
+        address: u8,
+    ) -> Self {
+        let mut i2c_config = i2c::Config::default();
+        i2c_config.frequency = I2C_FS;
+        let i2c = i2c::I2c::new(i2c2, scl, sda, tx_dma, rx_dma, Bound, i2c_config);
+        Self { i2c, address }
+    }
+
+    /// Writes `bytes`, retrying on failure.
+    pub async fn write(&mut self, bytes: &[u8]) -> Result<(), CodecError> {
+        let mut attempt = 1;
+        loop {
+            match self.try_write(bytes).await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt < I2C_ATTEMPTS => self.backoff(e, attempt).await,
+                Err(e) => return Err(e),
+            }
+            attempt += 1;
+        }
+    }
+
+    /// Writes `bytes` and reads `buffer` in one transaction, retrying on failure.
+    #[cfg(feature = "patch_sm")]
+    pub async fn write_read(&mut self, bytes: &[u8], buffer: &mut [u8]) -> Result<(), CodecError> {
+        let mut attempt = 1;
+        loop {
+            let result = with_timeout(
+                I2C_TIMEOUT,
+                self.i2c.write_read(self.address, bytes, buffer),
+            )
+            .await;
+            match flatten(result) {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt < I2C_ATTEMPTS => self.backoff(e, attempt).await,
+                Err(e) => return Err(e),
+            }
+            attempt += 1;
+        }
+    }
+
+    /// Writes `bytes` once, without retrying. For probing whether the codec is there at all.
+    pub async fn try_write(&mut self, bytes: &[u8]) -> Result<(), CodecError> {
+        flatten(with_timeout(I2C_TIMEOUT, self.i2c.write(self.address, bytes)).await)
+    }
+
+    async fn backoff(&self, e: CodecError, attempt: u32) {
+        warn!(
+            "codec I2C transfer failed ({}), attempt {} of {}",
+            e, attempt, I2C_ATTEMPTS
+        );
+        Timer::after(RETRY_DELAY * (1 << (attempt - 1))).await;
+    }
+
+    pub fn release(self) -> i2c::I2c<'a, hal::mode::Async, i2c::Master> {
+        self.i2c
+    }
+}
+
+fn flatten<E>(result: Result<Result<(), i2c::Error>, E>) -> Result<(), CodecError> {
+    match result {
+        Ok(result) => result.map_err(CodecError::from),
+        Err(_) => Err(CodecError::Timeout),
+    }
+}
@@ -1,14 +1,33 @@
-#[cfg(feature = "seed")]
+//! Codec drivers, one per board feature, and the `AudioCodec` trait they implement.
+//!
+//! `audio::Interface` only talks to the codec through `AudioCodec`, so a driver for a codec on
+//! another board can be plugged in with `AudioPeripherals::prepare_interface_with_codec()`.
+use embassy_stm32::{
+    i2c,
+    sai::{self, MasterClockDivider},
+};
+
+use crate::audio::{AudioConfig, AudioPeripherals, Capabilities, Error, Fs};
+
+mod diagnostics;
+pub use diagnostics::{RegisterDump, SelfTestError, SerialFormat, TxRx, sai_format};
+
+#[cfg(any(feature = "seed_1_1", feature = "patch_sm", feature = "auto"))]
+mod i2c_bus;
+#[cfg(any(feature = "seed_1_1", feature = "patch_sm", feature = "auto"))]
+pub use i2c_bus::CodecI2cIrqs;
+
+#[cfg(any(feature = "seed", feature = "auto"))]
 mod ak4556;
 #[cfg(feature = "seed")]
 pub use ak4556::{Codec, Pins};
 
-#[cfg(feature = "seed_1_1")]
+#[cfg(any(feature = "seed_1_1", feature = "auto"))]
 mod wm8731;
 #[cfg(feature = "seed_1_1")]
-pub use wm8731::{Codec, Pins};
+pub use wm8731::{Codec, InputSelect, Pins, SidetoneAttenuation};
 
-#[cfg(feature = "seed_1_2")]
+#[cfg(any(feature = "seed_1_2", feature = "auto"))]
 mod pcm3060;
 #[cfg(feature = "seed_1_2")]
 pub use pcm3060::{Codec, Pins};
@@ -16,4 +35,148 @@ pub use pcm3060::{Codec, Pins};
 #[cfg(feature = "patch_sm")]
 mod pcm3060_i2c;
 #[cfg(feature = "patch_sm")]
-pub use pcm3060_i2c::{Codec, Pins};
+pub use pcm3060_i2c::{Codec, DeEmphasis, Pins, Rolloff, ZeroDetection};
+
+#[cfg(feature = "auto")]
+mod auto;
+#[cfg(feature = "auto")]
+pub use auto::{Codec, Pins, Revision};
+#[cfg(feature = "auto")]
+pub use wm8731::{Codec as Wm8731, InputSelect, SidetoneAttenuation};
+
+/// Channel selection for codec controls.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Channel {
+    Left,
+    Right,
+    Both,
+}
+
+/// Why the codec could not be configured over I2C.
+///
+/// Each register access is retried a few times, with a growing delay, before this is returned.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum CodecError {
+    /// The codec did not acknowledge its address or a register byte.
+    Nack,
+    /// The transfer did not complete in time, e.g. SCL held low.
+    Timeout,
+    /// Another master drove the bus.
+    Arbitration,
+    /// Misplaced start or stop condition, or another bus error.
+    Bus,
+}
+
+impl From<i2c::Error> for CodecError {
+    fn from(e: i2c::Error) -> Self {
+        match e {
+            i2c::Error::Nack => CodecError::Nack,
+            i2c::Error::Timeout => CodecError::Timeout,
+            i2c::Error::Arbitration => CodecError::Arbitration,
+            _ => CodecError::Bus,
+        }
+    }
+}
+
+/// Power state of a codec, as driven by `Interface`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum PowerState {
+    /// Configured by `new()`, not started yet.
+    Standby,
+    /// Running, after `start()` or `resume()`.
+    Active,
+    /// Output powered down or muted by `stop()`.
+    Stopped,
+    /// ADC and DAC powered down by `suspend()`, MCLK stopped.
+    Suspended,
+}
+
+/// A codec driver on SAI1.
+///
+/// The driver owns SAI1 and sets up both sub-blocks in `new()`: one of them the clock master with
+/// MCLK, the other synchronous to it, 24-bit data in 32-bit slots with two channels per frame.
+/// `Interface` pauses, resumes and reclocks the SAI itself, so the driver only has to keep the
+/// codec in step through `stop()`, `resume()` and `set_sample_rate()`.
+#[allow(async_fn_in_trait)] // the audio task runs on a single-threaded executor
+pub trait AudioCodec<'a>: Sized {
+    /// Pins the codec is wired to, passed in as `AudioPeripherals::codec_pins`.
+    type Pins;
+
+    /// Sample rates and clock ratios the codec accepts. `Interface` rejects anything else.
+    const CAPABILITIES: Capabilities;
+
+    /// DMA1 stream of the receiver, `0` for `dma1_ch0` or `1` for `dma1_ch1`. The transmitter
+    /// uses the other one. Both are followed by `Interface::start_callback_in_place`.
+    const RX_DMA_STREAM: usize;
+
+    /// Sets up the SAI and the codec. `tx_buffer` and `rx_buffer` are the DMA rings.
+    async fn new(
+        p: AudioPeripherals<'a, Self::Pins>,
+        audio_config: AudioConfig,
+        tx_buffer: &'a mut [u32],
+        rx_buffer: &'a mut [u32],
+    ) -> Result<Self, CodecError>;
+
+    /// Starts the codec and the SAI. Called once, by the first `Interface::start_interface()`.
+    async fn start(&mut self) -> Result<(), Error>;
+
+    /// Powers the output down or mutes it. Called with the SAI paused.
+    async fn stop(&mut self) -> Result<(), CodecError>;
+
+    /// Undoes `stop()`. Called before the SAI resumes.
+    async fn resume(&mut self) -> Result<(), CodecError>;
+
+    /// Powers the ADC and DAC down for `Interface::suspend()`, keeping the register settings.
+    /// Called with the SAI paused, before MCLK stops. Defaults to `stop()`.
+    async fn suspend(&mut self) -> Result<(), CodecError> {
+        self.stop().await
+    }
+
+    /// Undoes `suspend()` without running the setup of `new()` again.
+    /// Called after MCLK has restarted, before the SAI resumes. Defaults to `resume()`.
+    async fn wake(&mut self) -> Result<(), CodecError> {
+        self.resume().await
+    }
+
+    /// Reconfigures the codec for `fs`. Called with the SAI paused and already reclocked.
+    async fn set_sample_rate(&mut self, fs: Fs) -> Result<(), CodecError>;
+
+    /// Whether `set_mute()` has any effect.
+    fn can_mute(&self) -> bool {
+        false
+    }
+
+    /// Mutes or unmutes the output, independent of `stop()` and `resume()`.
+    /// Codecs without a mute control ignore this.
+    async fn set_mute(&mut self, _mute: bool) -> Result<(), CodecError> {
+        Ok(())
+    }
+
+    fn power_state(&self) -> PowerState;
+
+    /// The serial format the codec is set up for, read back from the codec where it can be.
+    async fn serial_format(&mut self) -> Result<SerialFormat, CodecError>;
+
+    /// Logs the codec registers. Codecs without a control interface have none.
+    async fn log_registers(&mut self) -> Result<(), CodecError> {
+        defmt::info!("codec has no control registers");
+        Ok(())
+    }
+
+    /// Checks that both SAI sub-blocks are set up for the format of `serial_format()`.
+    async fn self_test(&mut self) -> Result<(), SelfTestError> {
+        let format = self.serial_format().await?;
+        diagnostics::check_format(format, self.sai_tx_config(), self.sai_rx_config())
+    }
+
+    async fn read(&mut self, read_buf: &mut [u32]) -> Result<(), sai::Error>;
+
+    async fn write(&mut self, write_buf: &[u32]) -> Result<(), sai::Error>;
+
+    fn sai_tx_config(&self) -> &sai::Config;
+
+    fn sai_rx_config(&self) -> &sai::Config;
+
+    /// Records a new divider in the SAI configurations. `Interface` writes the registers.
+    fn set_master_clock_divider(&mut self, divider: MasterClockDivider);
+}
@@ -1,4 +1,7 @@
-use crate::audio::{AudioConfig, AudioIrqs, AudioPeripherals, HALF_DMA_BUFFER_LENGTH};
+use super::{AudioCodec, CodecError, PowerState, SerialFormat};
+use crate::audio::{
+    AudioConfig, AudioIrqs, AudioPeripherals, Capabilities, ClockRatio, Error, Fs, SILENCE,
+};
 use defmt::info;
 use embassy_stm32::{self as hal, Peri, peripherals, sai};
 use hal::peripherals::*;
@@ -9,16 +12,62 @@ pub struct Codec<'a> {
     sai_rx: sai::Sai<'a, peripherals::SAI1, u32>,
     pub sai_tx_config: sai::Config,
     pub sai_rx_config: sai::Config,
+    half_dma_buffer_length: usize,
+    power: PowerState,
 }
 
 impl<'a> Codec<'a> {
-    pub async fn new(
-        p: AudioPeripherals<'a>,
+    pub fn release(self) -> (sai::Sai<'a, SAI1, u32>, sai::Sai<'a, SAI1, u32>) {
+        (self.sai_tx, self.sai_rx)
+    }
+}
+
+impl<'a> AudioCodec<'a> for Codec<'a> {
+    type Pins = Pins<'a>;
+
+    /// The PCM3060 ADC runs from 16kHz to 96kHz. MCLK must stay below 36.864MHz,
+    /// so 512 x fs is limited to 48kHz. 192kHz on the DAC would need 128 x fs,
+    /// which the SAI cannot produce.
+    const CAPABILITIES: Capabilities = Capabilities {
+        modes: &[
+            (
+                ClockRatio::Ratio256,
+                &[
+                    Fs::Fs16000,
+                    Fs::Fs22050,
+                    Fs::Fs24000,
+                    Fs::Fs32000,
+                    Fs::Fs44100,
+                    Fs::Fs48000,
+                    Fs::Fs88200,
+                    Fs::Fs96000,
+                ],
+            ),
+            (
+                ClockRatio::Ratio512,
+                &[
+                    Fs::Fs16000,
+                    Fs::Fs22050,
+                    Fs::Fs24000,
+                    Fs::Fs32000,
+                    Fs::Fs44100,
+                    Fs::Fs48000,
+                ],
+            ),
+        ],
+    };
+
+    const RX_DMA_STREAM: usize = 1;
+
+    async fn new(
+        p: AudioPeripherals<'a, Pins<'a>>,
         audio_config: AudioConfig,
         tx_buffer: &'a mut [u32],
         rx_buffer: &'a mut [u32],
-    ) -> Self {
+    ) -> Result<Self, CodecError> {
         info!("set up PCM3060");
+        let half_dma_buffer_length = tx_buffer.len() / 2;
+
         info!("set up sai");
         let (sub_block_tx, sub_block_rx) = hal::sai::split_subblocks(p.sai1);
         let mut sai_tx_config = hal::sai::Config::default();
@@ -26,7 +75,7 @@ impl<'a> Codec<'a> {
         sai_tx_config.tx_rx = sai::TxRx::Transmitter;
         sai_tx_config.sync_output = true;
         sai_tx_config.clock_strobe = sai::ClockStrobe::Falling;
-        sai_tx_config.master_clock_divider = audio_config.fs.into_clock_divider();
+        sai_tx_config.master_clock_divider = audio_config.master_clock_divider();
         sai_tx_config.stereo_mono = sai::StereoMono::Stereo;
         sai_tx_config.data_size = sai::DataSize::Data24;
         sai_tx_config.bit_order = sai::BitOrder::MsbFirst;
@@ -63,15 +112,17 @@ impl<'a> Codec<'a> {
             sai_rx_config,
         );
 
-        Self {
+        Ok(Self {
             sai_tx,
             sai_rx,
             sai_tx_config,
             sai_rx_config,
-        }
+            half_dma_buffer_length,
+            power: PowerState::Standby,
+        })
     }
 
-    pub async fn start(&mut self) -> Result<(), sai::Error> {
+    async fn start(&mut self) -> Result<(), Error> {
         info!("start SAI");
 
         // As the SAI configuration for the PCM3060
@@ -80,18 +131,70 @@ impl<'a> Codec<'a> {
         // as well for the slave SAI to work.
         // As of embassy-stm32 v0.2.0 this can only
         // be done by writing to the transmitter once.
-        let write_buf = [0; HALF_DMA_BUFFER_LENGTH];
-        self.sai_tx.write(&write_buf).await?;
-        self.sai_rx.start()
+        let write_buf = &SILENCE[..self.half_dma_buffer_length];
+        self.sai_tx.write(write_buf).await?;
+        self.power = PowerState::Active;
+        Ok(self.sai_rx.start()?)
     }
 
-    pub async fn read(&mut self, read_buf: &mut [u32]) -> Result<(), sai::Error> {
+    /// The PCM3060 detects the sampling rate from the SAI clocks, so there is nothing to reconfigure.
+    async fn set_sample_rate(&mut self, _fs: Fs) -> Result<(), CodecError> {
+        Ok(())
+    }
+
+    /// Without a control interface the PCM3060 cannot be muted. The SAI is paused with silence
+    /// in the DMA ring, so the output stays quiet anyway.
+    async fn stop(&mut self) -> Result<(), CodecError> {
+        self.power = PowerState::Stopped;
+        Ok(())
+    }
+
+    async fn resume(&mut self) -> Result<(), CodecError> {
+        self.power = PowerState::Active;
+        Ok(())
+    }
+
+    /// The PCM3060 powers itself down when MCLK stops, which `Interface::suspend()` does next.
+    async fn suspend(&mut self) -> Result<(), CodecError> {
+        self.power = PowerState::Suspended;
+        Ok(())
+    }
+
+    /// The PCM3060 powers up by itself once MCLK runs again.
+    async fn wake(&mut self) -> Result<(), CodecError> {
+        self.power = PowerState::Active;
+        Ok(())
+    }
+
+    fn power_state(&self) -> PowerState {
+        self.power
+    }
+
+    /// Fixed to 24-bit left-justified by the FMT pin on the Seed 1.2.
+    async fn serial_format(&mut self) -> Result<SerialFormat, CodecError> {
+        Ok(SerialFormat::LeftJustified24)
+    }
+
+    async fn read(&mut self, read_buf: &mut [u32]) -> Result<(), sai::Error> {
         self.sai_rx.read(read_buf).await
     }
 
-    pub async fn write(&mut self, write_buf: &[u32]) -> Result<(), sai::Error> {
+    async fn write(&mut self, write_buf: &[u32]) -> Result<(), sai::Error> {
         self.sai_tx.write(write_buf).await
     }
+
+    fn sai_tx_config(&self) -> &sai::Config {
+        &self.sai_tx_config
+    }
+
+    fn sai_rx_config(&self) -> &sai::Config {
+        &self.sai_rx_config
+    }
+
+    fn set_master_clock_divider(&mut self, divider: sai::MasterClockDivider) {
+        self.sai_tx_config.master_clock_divider = divider;
+        self.sai_rx_config.master_clock_divider = divider;
+    }
 }
 
 #[allow(non_snake_case)]
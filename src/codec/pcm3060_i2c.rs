@@ -1,21 +1,29 @@
 //! A simple HAL for the Texas Instruments PCM3060 audio codec
-use crate::audio::{AudioConfig, AudioIrqs, AudioPeripherals, HALF_DMA_BUFFER_LENGTH};
+use super::{
+    AudioCodec, Channel, CodecError, PowerState, RegisterDump, SerialFormat, i2c_bus::CodecI2c,
+};
+use crate::audio::{
+    AudioConfig, AudioIrqs, AudioPeripherals, Capabilities, ClockRatio, Error, Fs, SILENCE,
+};
 use embassy_stm32::Peri;
-use embassy_stm32::{self as hal, peripherals, sai, time::Hertz};
+use embassy_stm32::{self as hal, peripherals, sai};
 use hal::peripherals::*;
 
-use defmt::{info, unwrap};
+use defmt::{info, warn};
 use embassy_time::Timer;
 
-const I2C_FS: Hertz = Hertz(100_000);
-
 // PCM3060 I2C constants
 const I2C_CODEC_ADDRESS: u8 = 0x8c >> 1;
 
 // PCM3060 register addresses
 const SYS_CTRL_REGISTER: u8 = 0x40; // 64
+const DAC_ATT_LEFT_REGISTER: u8 = 0x41; // 65
+const DAC_ATT_RIGHT_REGISTER: u8 = 0x42; // 66
 const ADC_CTRL1_REGISTER: u8 = 0x48; // 72
 const DAC_CTRL1_REGISTER: u8 = 0x43; // 67
+const DAC_CTRL2_REGISTER: u8 = 0x44; // 68
+const DAC_CTRL3_REGISTER: u8 = 0x45; // 69
+const REGISTER_COUNT: usize = 10; // 0x40 to 0x49
 
 // PCM3060 register masks
 const MRST_MASK: u8 = 0x80;
@@ -23,32 +31,273 @@ const SRST_MASK: u8 = 0x40;
 const ADC_PSV_MASK: u8 = 0x20;
 const DAC_PSV_MASK: u8 = 0x10;
 const FMT_MASK: u8 = 0x1;
+const FMT_FIELD_MASK: u8 = 0x3; // FMT1 (ADC) or FMT2 (DAC)
+const DAC_MUTE_MASK: u8 = 0x3; // MUT22 | MUT21
+const DAC_MUTE_LEFT_MASK: u8 = 0x1; // MUT21
+const DAC_MUTE_RIGHT_MASK: u8 = 0x2; // MUT22
+const FLT_MASK: u8 = 0x80; // FLT2, slow roll-off
+const DMF_MASK: u8 = 0x60; // DMF2, de-emphasis frequency
+const DMC_MASK: u8 = 0x10; // DMC2, de-emphasis enable
+const ZREV_MASK: u8 = 0x2; // ZREV2, zero flags active low
+const AZRO_MASK: u8 = 0x1; // AZRO2, one zero flag for both channels
+
+/// Roll-off of the DAC interpolation filter, see `Codec::set_rolloff()`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Rolloff {
+    /// The default.
+    Sharp,
+    Slow,
+}
+
+/// DAC de-emphasis, see `Codec::set_de_emphasis()`. The ADC has none.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum DeEmphasis {
+    Off,
+    Fs32000,
+    Fs44100,
+    Fs48000,
+}
+
+/// Behaviour of the ZERO1/ZERO2 pins, which signal a long run of zero samples on the DAC input.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct ZeroDetection {
+    /// ZERO1 is set when both channels are zero, instead of ZERO1 for left and ZERO2 for right.
+    pub combined: bool,
+    pub active_low: bool,
+}
 
 pub struct Codec<'a> {
-    i2c: hal::i2c::I2c<'a, hal::mode::Blocking, hal::i2c::Master>,
+    i2c: CodecI2c<'a>,
     sai_tx: sai::Sai<'a, peripherals::SAI1, u32>,
     sai_rx: sai::Sai<'a, peripherals::SAI1, u32>,
     pub sai_tx_config: sai::Config,
     pub sai_rx_config: sai::Config,
+    half_dma_buffer_length: usize,
+    // DAC_MUTE_MASK bits requested through `set_mute()` and `set_dac_mute()`
+    dac_mute: u8,
+    power: PowerState,
 }
 
 impl<'a> Codec<'a> {
-    pub async fn new(
-        p: AudioPeripherals<'a>,
+    async fn setup_pcm3060(&mut self) -> Result<(), CodecError> {
+        // Reset codec
+        self.write_pcm3060_reg(SYS_CTRL_REGISTER, MRST_MASK, false)
+            .await?;
+        self.write_pcm3060_reg(SYS_CTRL_REGISTER, SRST_MASK, false)
+            .await?;
+
+        // Set 24-bit Left-Justified ft
+        self.write_pcm3060_reg(ADC_CTRL1_REGISTER, FMT_MASK, true)
+            .await?;
+        self.write_pcm3060_reg(DAC_CTRL1_REGISTER, FMT_MASK, true)
+            .await?;
+
+        // Disable power saving
+        self.write_pcm3060_reg(SYS_CTRL_REGISTER, ADC_PSV_MASK, false)
+            .await?;
+        self.write_pcm3060_reg(SYS_CTRL_REGISTER, DAC_PSV_MASK, false)
+            .await
+    }
+
+    async fn write_pcm3060_reg(
+        &mut self,
+        register: u8,
+        mask: u8,
+        set: bool,
+    ) -> Result<(), CodecError> {
+        self.update_pcm3060_reg(register, mask, if set { mask } else { 0 })
+            .await
+    }
+
+    /// Replaces the bits in `mask` of `register` with those of `value`.
+    async fn update_pcm3060_reg(
+        &mut self,
+        register: u8,
+        mask: u8,
+        value: u8,
+    ) -> Result<(), CodecError> {
+        // Read current register value
+        let current = self.read_pcm3060_reg(register).await?;
+
+        // Modify value based on mask
+        let value = (current & !mask) | (value & mask);
+
+        // Write back modified value
+        self.i2c.write(&[register, value]).await?;
+
+        Timer::after_micros(10).await;
+        Ok(())
+    }
+
+    async fn read_pcm3060_reg(&mut self, register: u8) -> Result<u8, CodecError> {
+        let mut buffer = [0];
+        self.i2c.write_read(&[register], &mut buffer).await?;
+        Ok(buffer[0])
+    }
+
+    /// Reads back all registers, 0x40 to 0x49.
+    pub async fn read_registers(&mut self) -> Result<RegisterDump<u8, REGISTER_COUNT>, CodecError> {
+        let mut values = [0; REGISTER_COUNT];
+        for (register, value) in (SYS_CTRL_REGISTER..).zip(&mut values) {
+            *value = self.read_pcm3060_reg(register).await?;
+        }
+        Ok(RegisterDump {
+            first_address: SYS_CTRL_REGISTER,
+            values,
+        })
+    }
+
+    fn serial_format_of(value: u8) -> SerialFormat {
+        match value & FMT_FIELD_MASK {
+            0b00 => SerialFormat::I2s24,
+            0b01 => SerialFormat::LeftJustified24,
+            0b10 => SerialFormat::RightJustified24,
+            _ => SerialFormat::RightJustified16,
+        }
+    }
+
+    //====================runtime controls============================
+
+    /// DAC attenuation in dB, from 0dB down to -100dB in 0.5dB steps. Lower values mute the channel.
+    /// The PCM3060 steps to the new level one step at a time, so changes do not click.
+    pub async fn set_dac_attenuation(
+        &mut self,
+        channel: Channel,
+        db: f32,
+    ) -> Result<(), CodecError> {
+        // 255 is 0dB, 55 is -100dB and everything below mutes
+        let steps = (-db.clamp(-100.5, 0.0) * 2.0 + 0.5) as u8;
+        let level = 255 - steps;
+        if channel != Channel::Right {
+            self.update_pcm3060_reg(DAC_ATT_LEFT_REGISTER, 0xff, level)
+                .await?;
+        }
+        if channel != Channel::Left {
+            self.update_pcm3060_reg(DAC_ATT_RIGHT_REGISTER, 0xff, level)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Soft-mutes DAC channels. `AudioCodec::set_mute()` does the same for both channels.
+    /// Kept across `stop()` and `resume()`, and `suspend()` and `wake()`.
+    pub async fn set_dac_mute(&mut self, channel: Channel, mute: bool) -> Result<(), CodecError> {
+        let mask = match channel {
+            Channel::Left => DAC_MUTE_LEFT_MASK,
+            Channel::Right => DAC_MUTE_RIGHT_MASK,
+            Channel::Both => DAC_MUTE_MASK,
+        };
+        info!("PCM3060 mute {}: {}", channel, mute);
+        self.dac_mute = if mute {
+            self.dac_mute | mask
+        } else {
+            self.dac_mute & !mask
+        };
+        if !matches!(self.power, PowerState::Stopped | PowerState::Suspended) {
+            self.update_pcm3060_reg(DAC_CTRL2_REGISTER, DAC_MUTE_MASK, self.dac_mute)
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn set_de_emphasis(&mut self, de_emphasis: DeEmphasis) -> Result<(), CodecError> {
+        let value = match de_emphasis {
+            DeEmphasis::Off => 0,
+            DeEmphasis::Fs44100 => DMC_MASK,
+            DeEmphasis::Fs48000 => DMC_MASK | 0x20,
+            DeEmphasis::Fs32000 => DMC_MASK | 0x40,
+        };
+        self.update_pcm3060_reg(DAC_CTRL3_REGISTER, DMC_MASK | DMF_MASK, value)
+            .await
+    }
+
+    pub async fn set_rolloff(&mut self, rolloff: Rolloff) -> Result<(), CodecError> {
+        self.write_pcm3060_reg(DAC_CTRL3_REGISTER, FLT_MASK, rolloff == Rolloff::Slow)
+            .await
+    }
+
+    pub async fn set_zero_detection(
+        &mut self,
+        zero_detection: ZeroDetection,
+    ) -> Result<(), CodecError> {
+        let mut value = 0;
+        if zero_detection.combined {
+            value |= AZRO_MASK;
+        }
+        if zero_detection.active_low {
+            value |= ZREV_MASK;
+        }
+        self.update_pcm3060_reg(DAC_CTRL3_REGISTER, AZRO_MASK | ZREV_MASK, value)
+            .await
+    }
+
+    pub fn release(
+        self,
+    ) -> (
+        sai::Sai<'a, SAI1, u32>,
+        sai::Sai<'a, SAI1, u32>,
+        hal::i2c::I2c<'a, hal::mode::Async, hal::i2c::Master>,
+    ) {
+        (self.sai_tx, self.sai_rx, self.i2c.release())
+    }
+}
+
+impl<'a> AudioCodec<'a> for Codec<'a> {
+    type Pins = Pins<'a>;
+
+    /// The PCM3060 ADC runs from 16kHz to 96kHz. MCLK must stay below 36.864MHz,
+    /// so 512 x fs is limited to 48kHz. 192kHz on the DAC would need 128 x fs,
+    /// which the SAI cannot produce.
+    const CAPABILITIES: Capabilities = Capabilities {
+        modes: &[
+            (
+                ClockRatio::Ratio256,
+                &[
+                    Fs::Fs16000,
+                    Fs::Fs22050,
+                    Fs::Fs24000,
+                    Fs::Fs32000,
+                    Fs::Fs44100,
+                    Fs::Fs48000,
+                    Fs::Fs88200,
+                    Fs::Fs96000,
+                ],
+            ),
+            (
+                ClockRatio::Ratio512,
+                &[
+                    Fs::Fs16000,
+                    Fs::Fs22050,
+                    Fs::Fs24000,
+                    Fs::Fs32000,
+                    Fs::Fs44100,
+                    Fs::Fs48000,
+                ],
+            ),
+        ],
+    };
+
+    const RX_DMA_STREAM: usize = 0;
+
+    async fn new(
+        p: AudioPeripherals<'a, Pins<'a>>,
         audio_config: AudioConfig,
         tx_buffer: &'a mut [u32],
         rx_buffer: &'a mut [u32],
-    ) -> Self {
+    ) -> Result<Self, CodecError> {
         info!("set up i2c");
-        let mut i2c_config = hal::i2c::Config::default();
-        i2c_config.frequency = I2C_FS;
-        let i2c = embassy_stm32::i2c::I2c::new_blocking(
+        let i2c = CodecI2c::new(
             p.i2c2,
             p.codec_pins.SCL,
             p.codec_pins.SDA,
-            i2c_config,
+            p.dma1_ch4,
+            p.dma1_ch5,
+            p.i2c_irqs,
+            I2C_CODEC_ADDRESS,
         );
 
+        let half_dma_buffer_length = tx_buffer.len() / 2;
+
         info!("set up sai");
         let (sub_block_rx, sub_block_tx) = hal::sai::split_subblocks(p.sai1);
 
@@ -59,7 +308,7 @@ impl<'a> Codec<'a> {
         sai_rx_config.tx_rx = sai::TxRx::Receiver;
         sai_rx_config.sync_output = true;
         sai_rx_config.clock_strobe = sai::ClockStrobe::Rising;
-        sai_rx_config.master_clock_divider = audio_config.fs.into_clock_divider();
+        sai_rx_config.master_clock_divider = audio_config.master_clock_divider();
         sai_rx_config.stereo_mono = sai::StereoMono::Stereo;
         sai_rx_config.data_size = sai::DataSize::Data24;
         sai_rx_config.bit_order = sai::BitOrder::MsbFirst;
@@ -107,83 +356,122 @@ impl<'a> Codec<'a> {
             sai_rx,
             sai_tx_config,
             sai_rx_config,
+            half_dma_buffer_length,
+            dac_mute: 0,
+            power: PowerState::Standby,
         };
 
         info!("set up PCM3060 i2c");
-        codec.setup_pcm3060().await;
+        codec.setup_pcm3060().await?;
 
-        codec
+        Ok(codec)
     }
 
-    async fn setup_pcm3060(&mut self) {
-        // Reset codec
-        self.write_pcm3060_reg(SYS_CTRL_REGISTER, MRST_MASK, false)
-            .await;
-        self.write_pcm3060_reg(SYS_CTRL_REGISTER, SRST_MASK, false)
-            .await;
+    async fn start(&mut self) -> Result<(), Error> {
+        info!("start SAI");
 
-        // Set 24-bit Left-Justified ft
-        self.write_pcm3060_reg(ADC_CTRL1_REGISTER, FMT_MASK, true)
-            .await;
-        self.write_pcm3060_reg(DAC_CTRL1_REGISTER, FMT_MASK, true)
-            .await;
+        let write_buf = &SILENCE[..self.half_dma_buffer_length];
+        self.sai_tx.write(write_buf).await?;
+        self.power = PowerState::Active;
+        Ok(self.sai_rx.start()?)
+    }
 
-        // Disable power saving
-        self.write_pcm3060_reg(SYS_CTRL_REGISTER, ADC_PSV_MASK, false)
-            .await;
-        self.write_pcm3060_reg(SYS_CTRL_REGISTER, DAC_PSV_MASK, false)
-            .await;
+    /// The PCM3060 detects the sampling rate from the SAI clocks, so there is nothing to reconfigure.
+    async fn set_sample_rate(&mut self, _fs: Fs) -> Result<(), CodecError> {
+        Ok(())
     }
 
-    async fn write_pcm3060_reg(&mut self, register: u8, mask: u8, set: bool) {
-        // Read current register value
-        let mut buffer = [0];
-        unwrap!(
-            self.i2c
-                .blocking_write_read(I2C_CODEC_ADDRESS, &[register], &mut buffer)
-        );
+    /// Soft-mutes both DAC channels. Called with the SAI paused.
+    async fn stop(&mut self) -> Result<(), CodecError> {
+        info!("mute PCM3060");
+        self.write_pcm3060_reg(DAC_CTRL2_REGISTER, DAC_MUTE_MASK, true)
+            .await?;
+        self.power = PowerState::Stopped;
+        Ok(())
+    }
 
-        // Modify value based on mask and set flag
-        let value = if set {
-            buffer[0] | mask
-        } else {
-            buffer[0] & !mask
-        };
+    /// Releases the mute set by `stop()`, except for channels muted with `set_mute()` or
+    /// `set_dac_mute()`. Called before the SAI resumes.
+    async fn resume(&mut self) -> Result<(), CodecError> {
+        info!("unmute PCM3060");
+        self.update_pcm3060_reg(DAC_CTRL2_REGISTER, DAC_MUTE_MASK, self.dac_mute)
+            .await?;
+        self.power = PowerState::Active;
+        Ok(())
+    }
 
-        // Write back modified value
-        unwrap!(
-            self.i2c
-                .blocking_write(I2C_CODEC_ADDRESS, &[register, value])
-        );
+    /// Mutes the DAC, then puts ADC and DAC into power-save. Registers are retained.
+    async fn suspend(&mut self) -> Result<(), CodecError> {
+        info!("suspend PCM3060");
+        self.write_pcm3060_reg(DAC_CTRL2_REGISTER, DAC_MUTE_MASK, true)
+            .await?;
+        self.write_pcm3060_reg(SYS_CTRL_REGISTER, ADC_PSV_MASK | DAC_PSV_MASK, true)
+            .await?;
+        self.power = PowerState::Suspended;
+        Ok(())
+    }
 
-        Timer::after_micros(10).await;
+    /// Leaves power-save and restores the mute of `set_dac_mute()`.
+    async fn wake(&mut self) -> Result<(), CodecError> {
+        info!("wake PCM3060");
+        self.write_pcm3060_reg(SYS_CTRL_REGISTER, ADC_PSV_MASK | DAC_PSV_MASK, false)
+            .await?;
+        self.update_pcm3060_reg(DAC_CTRL2_REGISTER, DAC_MUTE_MASK, self.dac_mute)
+            .await?;
+        self.power = PowerState::Active;
+        Ok(())
     }
 
-    pub async fn start(&mut self) -> Result<(), sai::Error> {
-        info!("start SAI");
+    fn power_state(&self) -> PowerState {
+        self.power
+    }
 
-        let write_buf = [0; HALF_DMA_BUFFER_LENGTH];
-        self.sai_tx.write(&write_buf).await?;
-        self.sai_rx.start()
+    /// Read back from the format fields of the ADC and the DAC. `SerialFormat::Other` if they differ.
+    async fn serial_format(&mut self) -> Result<SerialFormat, CodecError> {
+        let adc = Self::serial_format_of(self.read_pcm3060_reg(ADC_CTRL1_REGISTER).await?);
+        let dac = Self::serial_format_of(self.read_pcm3060_reg(DAC_CTRL1_REGISTER).await?);
+        if adc != dac {
+            warn!("PCM3060 ADC format {} differs from DAC format {}", adc, dac);
+            return Ok(SerialFormat::Other);
+        }
+        Ok(dac)
     }
 
-    pub fn release(
-        self,
-    ) -> (
-        sai::Sai<'a, SAI1, u32>,
-        sai::Sai<'a, SAI1, u32>,
-        hal::i2c::I2c<'a, hal::mode::Blocking, hal::i2c::Master>,
-    ) {
-        (self.sai_tx, self.sai_rx, self.i2c)
+    async fn log_registers(&mut self) -> Result<(), CodecError> {
+        let registers = self.read_registers().await?;
+        info!("PCM3060 registers:{}", registers);
+        Ok(())
+    }
+
+    fn can_mute(&self) -> bool {
+        true
+    }
+
+    /// Soft-mutes both DAC channels. Kept across `stop()` and `resume()`.
+    async fn set_mute(&mut self, mute: bool) -> Result<(), CodecError> {
+        self.set_dac_mute(Channel::Both, mute).await
     }
 
-    pub async fn read(&mut self, read_buf: &mut [u32]) -> Result<(), sai::Error> {
+    async fn read(&mut self, read_buf: &mut [u32]) -> Result<(), sai::Error> {
         self.sai_rx.read(read_buf).await
     }
 
-    pub async fn write(&mut self, write_buf: &[u32]) -> Result<(), sai::Error> {
+    async fn write(&mut self, write_buf: &[u32]) -> Result<(), sai::Error> {
         self.sai_tx.write(write_buf).await
     }
+
+    fn sai_tx_config(&self) -> &sai::Config {
+        &self.sai_tx_config
+    }
+
+    fn sai_rx_config(&self) -> &sai::Config {
+        &self.sai_rx_config
+    }
+
+    fn set_master_clock_divider(&mut self, divider: sai::MasterClockDivider) {
+        self.sai_tx_config.master_clock_divider = divider;
+        self.sai_rx_config.master_clock_divider = divider;
+    }
 }
 
 #[allow(non_snake_case)]
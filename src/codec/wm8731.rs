@@ -1,44 +1,400 @@
+use core::ops::RangeInclusive;
+
 use embassy_stm32::{
     self as hal, Peri, peripherals,
     sai::{
         self, BitOrder, ClockStrobe, DataSize, FifoThreshold, FrameSyncOffset, FrameSyncPolarity,
         Mode, StereoMono, SyncInput, TxRx,
     },
-    time::Hertz,
 };
 use hal::peripherals::*;
 
-use defmt::{info, unwrap};
+use defmt::info;
 use embassy_time::Timer;
 
-use crate::audio::{AudioConfig, AudioIrqs, AudioPeripherals, Fs};
+use super::{
+    AudioCodec, Channel, CodecError, PowerState, RegisterDump, SerialFormat, i2c_bus::CodecI2c,
+};
+use crate::audio::{AudioConfig, AudioIrqs, AudioPeripherals, Capabilities, ClockRatio, Error, Fs};
+
+pub(crate) const I2C_ADDRESS: u8 = 0x1a; // or 0x1b if CSB is high
+
+// WM8731 register addresses
+const LEFT_LINE_IN: u8 = 0x00;
+const LEFT_HEADPHONE_OUT: u8 = 0x02;
+const ANALOG_AUDIO_PATH: u8 = 0x04;
+const DIGITAL_AUDIO_PATH: u8 = 0x05;
+const DIGITAL_AUDIO_INTERFACE_FORMAT: u8 = 0x07;
+const RESET: u8 = 0x0f;
+
+// WM8731 register fields
+const LINE_IN_VOLUME_MASK: u16 = 0x1f;
+const LINE_IN_MUTE: u16 = 1 << 7;
+const HEADPHONE_VOLUME_MASK: u16 = 0x7f;
+const HEADPHONE_ZERO_CROSS: u16 = 1 << 7;
+const BOTH_CHANNELS: u16 = 1 << 8; // writes the other channel's register too
+const MIC_BOOST: u16 = 1 << 0;
+const MUTE_MIC: u16 = 1 << 1;
+const INPUT_SELECT_MIC: u16 = 1 << 2;
+const BYPASS: u16 = 1 << 3;
+const SIDETONE: u16 = 1 << 5;
+const SIDETONE_ATTENUATION_MASK: u16 = 0b11 << 6;
+const DAC_MUTE: u16 = 1 << 3;
+const FORMAT_MASK: u16 = 0b11;
+const WORD_LENGTH_MASK: u16 = 0b11 << 2;
+const CORE_CLOCK_DIVIDE_BY_2: u16 = 1 << 6; // CLKIDIV2 in the sampling control register
+
+/// Register contents after reset, R0 to R9 (datasheet table 29).
+const RESET_VALUES: [u16; 10] = [
+    0x097, 0x097, 0x079, 0x079, 0x00a, 0x008, 0x09f, 0x00a, 0x000, 0x000,
+];
+
+/// Source of the ADC, see `Codec::set_input()`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum InputSelect {
+    LineIn,
+    Mic,
+}
 
-const I2C_FS: Hertz = Hertz(100_000);
+/// Attenuation of the mic signal mixed into the output, see `Codec::set_sidetone()`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum SidetoneAttenuation {
+    Minus6dB,
+    Minus9dB,
+    Minus12dB,
+    Minus15dB,
+}
 
 /// A simple HAL for the Cirrus Logic/ Wolfson WM8731 audio codec
 pub struct Codec<'a> {
-    i2c: hal::i2c::I2c<'a, hal::mode::Blocking, hal::i2c::Master>,
+    i2c: CodecI2c<'a>,
     sai_tx: sai::Sai<'a, peripherals::SAI1, u32>,
     sai_rx: sai::Sai<'a, peripherals::SAI1, u32>,
     pub sai_tx_config: sai::Config,
     pub sai_rx_config: sai::Config,
+    // The WM8731 cannot be read back, so every write is mirrored here.
+    registers: [u16; 10],
+    power: PowerState,
 }
 
 impl<'a> Codec<'a> {
-    pub async fn new(
-        p: AudioPeripherals<'a>,
+    //====================wm8731 register set up functions============================
+    async fn setup_wm8731(&mut self, fs: Fs) -> Result<(), CodecError> {
+        use wm8731::WM8731;
+        info!("setup wm8731 from I2C");
+
+        Timer::after_micros(10).await;
+
+        // reset
+        self.write_wm8731_reg(WM8731::reset()).await?;
+        Timer::after_micros(10).await;
+
+        // wakeup
+        self.write_wm8731_reg(WM8731::power_down(|w| {
+            Self::final_power_settings(w);
+            //output off before start()
+            w.output().power_off();
+        }))
+        .await?;
+        Timer::after_micros(10).await;
+
+        // disable input mute, set to 0dB gain
+        self.write_wm8731_reg(WM8731::left_line_in(|w| {
+            w.both().enable();
+            w.mute().disable();
+            w.volume().nearest_dB(0);
+        }))
+        .await?;
+        Timer::after_micros(10).await;
+
+        // sidetone off; DAC selected; bypass off; line input selected; mic muted; mic boost off
+        self.write_wm8731_reg(WM8731::analog_audio_path(|w| {
+            w.sidetone().disable();
+            w.dac_select().select();
+            w.bypass().disable();
+            w.input_select().line_input();
+            w.mute_mic().enable();
+            w.mic_boost().disable();
+        }))
+        .await?;
+        Timer::after_micros(10).await;
+
+        // disable DAC mute, deemphasis matching fs
+        self.write_wm8731_reg(Self::digital_audio_path(fs, false))
+            .await?;
+        Timer::after_micros(10).await;
+
+        // nothing inverted, slave, 24-bits, MSB format
+        self.write_wm8731_reg(WM8731::digital_audio_interface_format(|w| {
+            w.bit_clock_invert().no_invert();
+            w.master_slave().slave();
+            w.left_right_dac_clock_swap().right_channel_dac_data_right();
+            w.left_right_phase().data_when_daclrc_low();
+            w.bit_length().bits_24();
+            w.format().left_justified();
+        }))
+        .await?;
+        Timer::after_micros(10).await;
+
+        // no clock division, normal mode
+        self.write_wm8731_reg(Self::sampling(fs)).await?;
+        Timer::after_micros(10).await;
+
+        // set active
+        self.write_wm8731_reg(WM8731::active().active()).await?;
+        Timer::after_micros(10).await;
+
+        //Note: WM8731's output not yet enabled.
+        Ok(())
+    }
+
+    fn digital_audio_path(fs: Fs, mute: bool) -> wm8731::Register {
+        wm8731::WM8731::digital_audio_path(|w| {
+            if mute {
+                w.dac_mut().enable();
+            } else {
+                w.dac_mut().disable();
+            }
+            match fs {
+                Fs::Fs32000 => {
+                    w.deemphasis().frequency_32();
+                }
+                Fs::Fs44100 => {
+                    w.deemphasis().frequency_441();
+                }
+                Fs::Fs48000 => {
+                    w.deemphasis().frequency_48();
+                }
+                _ => {
+                    w.deemphasis().disable();
+                }
+            }
+        })
+    }
+
+    /// See `CAPABILITIES` for the rates and how they are clocked.
+    fn sampling(fs: Fs) -> wm8731::Register {
+        let mut register = wm8731::WM8731::sampling(|w| {
+            w.core_clock_divider_select().normal();
+            w.base_oversampling_rate().normal_256();
+            match fs {
+                Fs::Fs44100 => {
+                    w.sample_rate().adc_441();
+                }
+                Fs::Fs48000 => {
+                    w.sample_rate().adc_48();
+                }
+                Fs::Fs88200 => {
+                    w.sample_rate().adc_882();
+                }
+                Fs::Fs96000 => {
+                    w.sample_rate().adc_96();
+                }
+                _ => defmt::unreachable!("rejected by Codec::CAPABILITIES"),
+            }
+            w.usb_normal().normal();
+        });
+        if matches!(fs, Fs::Fs88200 | Fs::Fs96000) {
+            register.value |= CORE_CLOCK_DIVIDE_BY_2;
+        }
+        register
+    }
+
+    async fn write_wm8731_reg(&mut self, r: wm8731::Register) -> Result<(), CodecError> {
+        self.write_raw(r.address, r.value).await
+    }
+
+    /// Writes a register and mirrors it in `registers`, which are left alone if the write fails.
+    async fn write_raw(&mut self, address: u8, value: u16) -> Result<(), CodecError> {
+        // WM8731 has 16 bits registers.
+        // The first 7 bits are for the addresses, and the rest 9 bits are for the "value"s.
+        let byte1: u8 = ((address << 1) & 0b1111_1110) | (((value >> 8) & 0b0000_0001) as u8);
+        let byte2: u8 = (value & 0b1111_1111) as u8;
+        self.i2c.write(&[byte1, byte2]).await?;
+
+        let value = value & 0x1ff;
+        match address {
+            RESET => self.registers = RESET_VALUES,
+            // line input and headphone registers with BOTH_CHANNELS load the other channel too
+            LEFT_LINE_IN..ANALOG_AUDIO_PATH if value & BOTH_CHANNELS != 0 => {
+                self.registers[address as usize] = value;
+                self.registers[(address ^ 1) as usize] = value;
+            }
+            _ => self.registers[address as usize] = value,
+        }
+        Ok(())
+    }
+
+    /// Replaces the bits in `mask` of the cached register with `value` and writes it.
+    async fn modify_reg(&mut self, address: u8, mask: u16, value: u16) -> Result<(), CodecError> {
+        let value = (self.registers[address as usize] & !mask) | (value & mask);
+        self.write_raw(address, value).await?;
+        Timer::after_micros(10).await;
+        Ok(())
+    }
+
+    //====================runtime controls============================
+
+    /// Headphone output volume in dB, from -73dB to +6dB in 1dB steps. Lower values mute the output.
+    /// Changes take effect at a zero crossing, so they do not click.
+    pub async fn set_headphone_volume(
+        &mut self,
+        channel: Channel,
+        db: i8,
+    ) -> Result<(), CodecError> {
+        // 0x79 is 0dB, 0x30 is -73dB and everything below mutes
+        let volume = (i16::from(db.clamp(-74, 6)) + 0x79) as u16;
+        for address in Self::registers_of(channel, LEFT_HEADPHONE_OUT) {
+            self.modify_reg(
+                address,
+                BOTH_CHANNELS | HEADPHONE_ZERO_CROSS | HEADPHONE_VOLUME_MASK,
+                HEADPHONE_ZERO_CROSS | volume,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Line input gain in dB, from -34.5dB to +12dB in 1.5dB steps. Rounds to the nearest step.
+    pub async fn set_line_in_gain(&mut self, channel: Channel, db: f32) -> Result<(), CodecError> {
+        // 0x17 is 0dB
+        let steps = (db.clamp(-34.5, 12.0) / 1.5 + 0.5 * db.signum()) as i16;
+        let volume = (steps + 0x17) as u16;
+        for address in Self::registers_of(channel, LEFT_LINE_IN) {
+            self.modify_reg(address, BOTH_CHANNELS | LINE_IN_VOLUME_MASK, volume)
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn set_line_in_mute(
+        &mut self,
+        channel: Channel,
+        mute: bool,
+    ) -> Result<(), CodecError> {
+        for address in Self::registers_of(channel, LEFT_LINE_IN) {
+            let value = if mute { LINE_IN_MUTE } else { 0 };
+            self.modify_reg(address, BOTH_CHANNELS | LINE_IN_MUTE, value)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Selects the ADC source. The mic input is muted until `set_mic_mute(false)`.
+    pub async fn set_input(&mut self, input: InputSelect) -> Result<(), CodecError> {
+        let value = match input {
+            InputSelect::LineIn => 0,
+            InputSelect::Mic => INPUT_SELECT_MIC,
+        };
+        self.modify_reg(ANALOG_AUDIO_PATH, INPUT_SELECT_MIC, value)
+            .await
+    }
+
+    pub async fn set_mic_mute(&mut self, mute: bool) -> Result<(), CodecError> {
+        let value = if mute { MUTE_MIC } else { 0 };
+        self.modify_reg(ANALOG_AUDIO_PATH, MUTE_MIC, value).await
+    }
+
+    /// Adds 20dB to the mic input.
+    pub async fn set_mic_boost(&mut self, boost: bool) -> Result<(), CodecError> {
+        let value = if boost { MIC_BOOST } else { 0 };
+        self.modify_reg(ANALOG_AUDIO_PATH, MIC_BOOST, value).await
+    }
+
+    /// Mixes the line input into the output, next to the DAC.
+    pub async fn set_bypass(&mut self, bypass: bool) -> Result<(), CodecError> {
+        let value = if bypass { BYPASS } else { 0 };
+        self.modify_reg(ANALOG_AUDIO_PATH, BYPASS, value).await
+    }
+
+    /// Mixes the mic input into the output at the given attenuation, or not at all with `None`.
+    pub async fn set_sidetone(
+        &mut self,
+        attenuation: Option<SidetoneAttenuation>,
+    ) -> Result<(), CodecError> {
+        let value = match attenuation {
+            None => 0,
+            Some(attenuation) => SIDETONE | ((attenuation as u16) << 6),
+        };
+        self.modify_reg(
+            ANALOG_AUDIO_PATH,
+            SIDETONE | SIDETONE_ATTENUATION_MASK,
+            value,
+        )
+        .await
+    }
+
+    /// The cached registers R0 to R9, as last written. The WM8731 cannot be read back,
+    /// so this shows what the driver sent, not what the codec latched.
+    pub fn registers(&self) -> RegisterDump<u16, 10> {
+        RegisterDump {
+            first_address: LEFT_LINE_IN,
+            values: self.registers,
+        }
+    }
+
+    /// `left` and the register after it hold the left and right channel.
+    /// The `BOTH_CHANNELS` bit is always cleared, so both registers are written one by one.
+    fn registers_of(channel: Channel, left: u8) -> RangeInclusive<u8> {
+        match channel {
+            Channel::Left => left..=left,
+            Channel::Right => left + 1..=left + 1,
+            Channel::Both => left..=left + 1,
+        }
+    }
+
+    fn final_power_settings(w: &mut wm8731::power_down::PowerDown) {
+        w.power_off().power_on();
+        w.clock_output().power_off();
+        w.oscillator().power_off();
+        w.output().power_on();
+        w.dac().power_on();
+        w.adc().power_on();
+        w.mic().power_off();
+        w.line_input().power_on();
+    }
+
+    pub fn release(
+        self,
+    ) -> (
+        sai::Sai<'a, SAI1, u32>,
+        sai::Sai<'a, SAI1, u32>,
+        hal::i2c::I2c<'a, hal::mode::Async, hal::i2c::Master>,
+    ) {
+        (self.sai_tx, self.sai_rx, self.i2c.release())
+    }
+}
+
+impl<'a> AudioCodec<'a> for Codec<'a> {
+    type Pins = Pins<'a>;
+
+    /// The driver runs the WM8731 in normal mode with a 256 x fs MCLK. In the datasheet's
+    /// sample rate table, that is 12.288MHz/11.2896MHz at 48kHz/44.1kHz, and twice that, halved
+    /// by CLKIDIV2, at 96kHz/88.2kHz. The lower rates of the table need a fixed 12.288MHz MCLK.
+    const CAPABILITIES: Capabilities = Capabilities {
+        modes: &[(
+            ClockRatio::Ratio256,
+            &[Fs::Fs44100, Fs::Fs48000, Fs::Fs88200, Fs::Fs96000],
+        )],
+    };
+
+    const RX_DMA_STREAM: usize = 1;
+
+    async fn new(
+        p: AudioPeripherals<'a, Pins<'a>>,
         audio_config: AudioConfig,
         tx_buffer: &'a mut [u32],
         rx_buffer: &'a mut [u32],
-    ) -> Self {
+    ) -> Result<Self, CodecError> {
         info!("set up i2c");
-        let mut i2c_config = hal::i2c::Config::default();
-        i2c_config.frequency = I2C_FS;
-        let i2c = embassy_stm32::i2c::I2c::new_blocking(
+        let i2c = CodecI2c::new(
             p.i2c2,
             p.codec_pins.SCL,
             p.codec_pins.SDA,
-            i2c_config,
+            p.dma1_ch4,
+            p.dma1_ch5,
+            p.i2c_irqs,
+            I2C_ADDRESS,
         );
 
         info!("set up sai");
@@ -48,7 +404,7 @@ impl<'a> Codec<'a> {
         sai_rx_config.tx_rx = TxRx::Receiver;
         sai_rx_config.sync_output = true;
         sai_rx_config.clock_strobe = ClockStrobe::Falling;
-        sai_rx_config.master_clock_divider = audio_config.fs.into_clock_divider();
+        sai_rx_config.master_clock_divider = audio_config.master_clock_divider();
         sai_rx_config.stereo_mono = StereoMono::Stereo;
         sai_rx_config.data_size = DataSize::Data24;
         sai_rx_config.bit_order = BitOrder::MsbFirst;
@@ -92,152 +448,181 @@ impl<'a> Codec<'a> {
             sai_rx,
             sai_tx_config,
             sai_rx_config,
+            registers: RESET_VALUES,
+            power: PowerState::Standby,
         };
 
-        codec.setup_wm8731(audio_config.fs).await;
+        codec.setup_wm8731(audio_config.fs).await?;
 
-        codec
+        Ok(codec)
     }
 
-    //====================wm8731 register set up functions============================
-    async fn setup_wm8731(&mut self, fs: Fs) {
+    /// Reprograms sampling control and de-emphasis for `fs`.
+    /// The codec is made inactive while the sampling control changes, as the datasheet recommends.
+    async fn set_sample_rate(&mut self, fs: Fs) -> Result<(), CodecError> {
         use wm8731::WM8731;
-        info!("setup wm8731 from I2C");
+        info!("set WM8731 sample rate");
 
+        self.write_wm8731_reg(WM8731::active().inactive()).await?;
         Timer::after_micros(10).await;
 
-        // reset
-        self.write_wm8731_reg(WM8731::reset());
+        let muted = self.registers[DIGITAL_AUDIO_PATH as usize] & DAC_MUTE != 0;
+        self.write_wm8731_reg(Self::digital_audio_path(fs, muted))
+            .await?;
         Timer::after_micros(10).await;
 
-        // wakeup
-        self.write_wm8731_reg(WM8731::power_down(|w| {
+        self.write_wm8731_reg(Self::sampling(fs)).await?;
+        Timer::after_micros(10).await;
+
+        self.write_wm8731_reg(WM8731::active().active()).await?;
+        Timer::after_micros(10).await;
+        Ok(())
+    }
+
+    async fn start(&mut self) -> Result<(), Error> {
+        info!("start WM8731");
+        self.write_wm8731_reg(wm8731::WM8731::power_down(Self::final_power_settings))
+            .await?;
+        Timer::after_micros(10).await;
+        self.power = PowerState::Active;
+
+        info!("start SAI");
+        Ok(self.sai_rx.start()?)
+    }
+
+    /// Powers the line output down, as before `start()`. Called with the SAI paused.
+    async fn stop(&mut self) -> Result<(), CodecError> {
+        info!("stop WM8731");
+        self.write_wm8731_reg(wm8731::WM8731::power_down(|w| {
             Self::final_power_settings(w);
-            //output off before start()
             w.output().power_off();
-        }));
+        }))
+        .await?;
         Timer::after_micros(10).await;
+        self.power = PowerState::Stopped;
+        Ok(())
+    }
 
-        // disable input mute, set to 0dB gain
-        self.write_wm8731_reg(WM8731::left_line_in(|w| {
-            w.both().enable();
-            w.mute().disable();
-            w.volume().nearest_dB(0);
-        }));
+    /// Powers the line output up again after `stop()`. Called before the SAI resumes.
+    async fn resume(&mut self) -> Result<(), CodecError> {
+        info!("resume WM8731");
+        self.write_wm8731_reg(wm8731::WM8731::power_down(Self::final_power_settings))
+            .await?;
         Timer::after_micros(10).await;
+        self.power = PowerState::Active;
+        Ok(())
+    }
 
-        // sidetone off; DAC selected; bypass off; line input selected; mic muted; mic boost off
-        self.write_wm8731_reg(WM8731::analog_audio_path(|w| {
-            w.sidetone().disable();
-            w.dac_select().select();
-            w.bypass().disable();
-            w.input_select().line_input();
-            w.mute_mic().enable();
-            w.mic_boost().disable();
-        }));
+    /// Powers the output down first, like `stop()`, then deactivates the digital interface and
+    /// powers down everything but the control interface. The registers keep their settings.
+    async fn suspend(&mut self) -> Result<(), CodecError> {
+        use wm8731::WM8731;
+        info!("suspend WM8731");
+        self.write_wm8731_reg(WM8731::power_down(|w| {
+            Self::final_power_settings(w);
+            w.output().power_off();
+        }))
+        .await?;
         Timer::after_micros(10).await;
 
-        // disable DAC mute, deemphasis for 48k
-        self.write_wm8731_reg(WM8731::digital_audio_path(|w| {
-            w.dac_mut().disable();
-            w.deemphasis().frequency_48();
-        }));
+        self.write_wm8731_reg(WM8731::active().inactive()).await?;
         Timer::after_micros(10).await;
 
-        // nothing inverted, slave, 24-bits, MSB format
-        self.write_wm8731_reg(WM8731::digital_audio_interface_format(|w| {
-            w.bit_clock_invert().no_invert();
-            w.master_slave().slave();
-            w.left_right_dac_clock_swap().right_channel_dac_data_right();
-            w.left_right_phase().data_when_daclrc_low();
-            w.bit_length().bits_24();
-            w.format().left_justified();
-        }));
+        self.write_wm8731_reg(WM8731::power_down(|w| {
+            w.power_off().power_on();
+            w.clock_output().power_off();
+            w.oscillator().power_off();
+            w.output().power_off();
+            w.dac().power_off();
+            w.adc().power_off();
+            w.mic().power_off();
+            w.line_input().power_off();
+        }))
+        .await?;
         Timer::after_micros(10).await;
+        self.power = PowerState::Suspended;
+        Ok(())
+    }
 
-        // no clock division, normal mode
-        self.write_wm8731_reg(WM8731::sampling(|w| {
-            w.core_clock_divider_select().normal();
-            w.base_oversampling_rate().normal_256();
-            match fs {
-                Fs::Fs8000 => {
-                    w.sample_rate().adc_8();
-                }
-                Fs::Fs32000 => {
-                    w.sample_rate().adc_32();
-                }
-                Fs::Fs44100 => {
-                    w.sample_rate().adc_441();
-                }
-                Fs::Fs48000 => {
-                    w.sample_rate().adc_48();
-                }
-                Fs::Fs88200 => {
-                    w.sample_rate().adc_882();
-                }
-                Fs::Fs96000 => {
-                    w.sample_rate().adc_96();
-                }
-            }
-            w.usb_normal().normal();
-        }));
+    /// Powers the paths up and reactivates the digital interface, the output last.
+    async fn wake(&mut self) -> Result<(), CodecError> {
+        use wm8731::WM8731;
+        info!("wake WM8731");
+        self.write_wm8731_reg(WM8731::power_down(|w| {
+            Self::final_power_settings(w);
+            w.output().power_off();
+        }))
+        .await?;
         Timer::after_micros(10).await;
 
-        // set active
-        self.write_wm8731_reg(WM8731::active().active());
+        self.write_wm8731_reg(WM8731::active().active()).await?;
         Timer::after_micros(10).await;
 
-        //Note: WM8731's output not yet enabled.
+        self.write_wm8731_reg(WM8731::power_down(Self::final_power_settings))
+            .await?;
+        Timer::after_micros(10).await;
+        self.power = PowerState::Active;
+        Ok(())
     }
 
-    fn write_wm8731_reg(&mut self, r: wm8731::Register) {
-        const AD: u8 = 0x1a; // or 0x1b if CSB is high
-
-        // WM8731 has 16 bits registers.
-        // The first 7 bits are for the addresses, and the rest 9 bits are for the "value"s.
-        // Let's pack wm8731::Register into 16 bits.
-        let byte1: u8 = ((r.address << 1) & 0b1111_1110) | (((r.value >> 8) & 0b0000_0001) as u8);
-        let byte2: u8 = (r.value & 0b1111_1111) as u8;
-        unwrap!(self.i2c.blocking_write(AD, &[byte1, byte2]));
+    fn power_state(&self) -> PowerState {
+        self.power
     }
 
-    fn final_power_settings(w: &mut wm8731::power_down::PowerDown) {
-        w.power_off().power_on();
-        w.clock_output().power_off();
-        w.oscillator().power_off();
-        w.output().power_on();
-        w.dac().power_on();
-        w.adc().power_on();
-        w.mic().power_off();
-        w.line_input().power_on();
+    /// Decoded from the cached digital audio interface format register.
+    async fn serial_format(&mut self) -> Result<SerialFormat, CodecError> {
+        let value = self.registers[DIGITAL_AUDIO_INTERFACE_FORMAT as usize];
+        Ok(
+            match (value & FORMAT_MASK, (value & WORD_LENGTH_MASK) >> 2) {
+                (0b01, 0b10) => SerialFormat::LeftJustified24,
+                (0b10, 0b10) => SerialFormat::I2s24,
+                (0b00, 0b10) => SerialFormat::RightJustified24,
+                (0b00, 0b00) => SerialFormat::RightJustified16,
+                _ => SerialFormat::Other,
+            },
+        )
     }
 
-    pub async fn start(&mut self) -> Result<(), sai::Error> {
-        info!("start WM8731");
-        self.write_wm8731_reg(wm8731::WM8731::power_down(Self::final_power_settings));
-        embassy_time::Timer::after_micros(10).await;
+    async fn log_registers(&mut self) -> Result<(), CodecError> {
+        info!("WM8731 registers (cached):{}", self.registers());
+        Ok(())
+    }
 
-        info!("start SAI");
-        self.sai_rx.start()
+    fn can_mute(&self) -> bool {
+        true
     }
 
-    pub fn release(
-        self,
-    ) -> (
-        sai::Sai<'a, SAI1, u32>,
-        sai::Sai<'a, SAI1, u32>,
-        hal::i2c::I2c<'a, hal::mode::Blocking, hal::i2c::Master>,
-    ) {
-        (self.sai_tx, self.sai_rx, self.i2c)
+    /// Soft-mutes the DAC. Kept across `set_sample_rate()`, `stop()` and `resume()`.
+    async fn set_mute(&mut self, mute: bool) -> Result<(), CodecError> {
+        info!("WM8731 mute: {}", mute);
+        self.modify_reg(
+            DIGITAL_AUDIO_PATH,
+            DAC_MUTE,
+            if mute { DAC_MUTE } else { 0 },
+        )
+        .await
     }
 
-    pub async fn read(&mut self, read_buf: &mut [u32]) -> Result<(), sai::Error> {
+    async fn read(&mut self, read_buf: &mut [u32]) -> Result<(), sai::Error> {
         self.sai_rx.read(read_buf).await
     }
 
-    pub async fn write(&mut self, write_buf: &[u32]) -> Result<(), sai::Error> {
+    async fn write(&mut self, write_buf: &[u32]) -> Result<(), sai::Error> {
         self.sai_tx.write(write_buf).await
     }
+
+    fn sai_tx_config(&self) -> &sai::Config {
+        &self.sai_tx_config
+    }
+
+    fn sai_rx_config(&self) -> &sai::Config {
+        &self.sai_rx_config
+    }
+
+    fn set_master_clock_divider(&mut self, divider: sai::MasterClockDivider) {
+        self.sai_tx_config.master_clock_divider = divider;
+        self.sai_rx_config.master_clock_divider = divider;
+    }
 }
 
 #[allow(non_snake_case)]
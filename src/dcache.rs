@@ -0,0 +1,36 @@
+//! D-cache maintenance around DMA transfers, shared by the audio buffers and the flash.
+use cortex_m::peripheral::SCB;
+
+/// Size of a Cortex-M7 D-cache line in bytes.
+pub(crate) const CACHE_LINE: usize = 32;
+
+/// Discards cached copies of `buf`, so the CPU sees what the DMA has written.
+/// `buf` has to start and end on a cache line.
+pub(crate) fn invalidate_dcache(buf: &mut [u32]) {
+    if SCB::dcache_enabled() {
+        // SAFETY: `buf` covers whole cache lines, so no other data is discarded.
+        // Cache maintenance does not conflict with other users of SCB.
+        unsafe {
+            cortex_m::Peripherals::steal()
+                .SCB
+                .invalidate_dcache_by_slice(buf)
+        };
+    }
+}
+
+/// Writes cached changes to `data` back to memory, so the DMA sees what the CPU has written.
+pub(crate) fn clean_dcache<T>(data: &[T]) {
+    let length = size_of_val(data);
+    if SCB::dcache_enabled() && length > 0 {
+        // `data` need not be aligned, so cover every line it touches.
+        let start = data.as_ptr() as usize & !(CACHE_LINE - 1);
+        let end = (data.as_ptr() as usize + length).next_multiple_of(CACHE_LINE);
+        // SAFETY: cleaning only writes dirty lines back, which is harmless for any address.
+        // Cache maintenance does not conflict with other users of SCB.
+        unsafe {
+            cortex_m::Peripherals::steal()
+                .SCB
+                .clean_dcache_by_address(start, end - start)
+        };
+    }
+}
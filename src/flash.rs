@@ -1,21 +1,33 @@
 //! Driver for the IS25LP064 Flash chip connected via QSPI
+//!
+//! `Flash` implements the `embedded-storage` `NorFlash` traits (blocking, and async for
+//! `Flash<'_, Async>`), so storage crates like `sequential-storage` or `ekv` can use it directly.
+//! Unlike `Flash::write`, their `write` only programs and never erases, like `Flash::program`.
+//!
+//! By default `Flash::write` erases whole 4 KiB sectors, losing the bytes around the written range.
+//! `Flash::set_write_mode(WriteMode::ReadModifyWrite(..))` keeps them instead.
+//!
 //! Note:
 //! The Daisy bootloader (as of v6.3) Does not use QPI mode, and configuring the flash chip that way would cause problems on reset. So for compatibility's sake, we do not use it here either.
-#![allow(unused)]
 
+use crate::dcache::clean_dcache;
 use crate::hal;
 use crate::pins::FlashPins;
 use embassy_stm32::{
     Peri,
     dma::{self},
-    interrupt::{self, typelevel::Binding},
+    interrupt::typelevel::Binding,
     mode::{Async, Mode},
     qspi::{
         Instance, InterruptHandler, MatchMode, QuadDma,
         enums::{AddressSize, ChipSelectHighTime, FIFOThresholdLevel, MemorySize},
     },
 };
-use embassy_time::{Duration, WithTimeout};
+use embassy_time::{Duration, Instant, WithTimeout};
+use embedded_storage::nor_flash::{
+    ErrorType, MultiwriteNorFlash, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+use embedded_storage_async::nor_flash as async_nor_flash;
 use hal::{
     mode::Blocking,
     peripherals::QUADSPI,
@@ -42,22 +54,18 @@ const STATUS_BIT_BP1: u8 = 1 << 3;
 const STATUS_BIT_BP2: u8 = 1 << 4;
 const STATUS_BIT_BP3: u8 = 1 << 5;
 const STATUS_BIT_QE: u8 = 1 << 6;
-const STATUS_BIT_SRWD: u8 = 1 << 7;
 
 const SET_READ_PARAMETERS_CMD: u8 = 0xC0; // SRP
-const READ_PARAMS_BIT_BL0: u8 = 1 << 0;
-const READ_PARAMS_BIT_BL1: u8 = 1 << 1;
-const READ_PARAMS_BIT_WE: u8 = 1 << 2;
-const READ_PARAMS_BIT_DC0: u8 = 1 << 3;
 const READ_PARAMS_BIT_DC1: u8 = 1 << 4;
 const READ_PARAMS_BIT_ODS0: u8 = 1 << 5;
 const READ_PARAMS_BIT_ODS1: u8 = 1 << 6;
 const READ_PARAMS_BIT_ODS2: u8 = 1 << 7;
 
 // Memory array specifications as defined in the datasheet.
-const SECTOR_SIZE: u32 = 4096;
+pub const SECTOR_SIZE: u32 = 4096;
 const PAGE_SIZE: u32 = 256;
 const MAX_ADDRESS: u32 = 0x7FFFFF;
+const CAPACITY: u32 = MAX_ADDRESS + 1;
 
 // Max Sector Erase time is 300ms
 const SECTOR_ERASE_TIMEOUT: Duration = Duration::from_millis(600);
@@ -65,25 +73,116 @@ const SECTOR_ERASE_TIMEOUT: Duration = Duration::from_millis(600);
 // Max Page Write time is 0.8ms
 const PAGE_WRITE_TIMEOUT: Duration = Duration::from_micros(1600);
 
+// Max Write Status Register time is 15ms
+const STATUS_WRITE_TIMEOUT: Duration = Duration::from_millis(30);
+
+/// Why a flash operation failed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum FlashError {
+    /// The range does not fit into the 8 MiB of the chip.
+    OutOfBounds,
+    /// An erase range does not start and end on a sector boundary.
+    NotAligned,
+    /// The chip stayed busy for longer than the datasheet's maximum program or erase time.
+    Timeout,
+    /// The chip did not accept the write enable, or block protection is set.
+    WriteProtected,
+    /// The data read back after `write()` differs from what was written.
+    VerifyFailed,
+    /// `program()` found bytes in the range that are not erased.
+    NotErased,
+}
+
+impl NorFlashError for FlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            FlashError::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            FlashError::NotAligned => NorFlashErrorKind::NotAligned,
+            _ => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+fn check_range(address: u32, length: usize) -> Result<(), FlashError> {
+    match u32::try_from(length) {
+        Ok(length) if address <= CAPACITY && length <= CAPACITY - address => Ok(()),
+        _ => Err(FlashError::OutOfBounds),
+    }
+}
+
+/// What it takes to bring a range of a sector to the new data.
+enum SectorUpdate {
+    Unchanged,
+    /// Programming only clears bits, so no erase is needed if no bit goes from 0 to 1.
+    Program,
+    Erase,
+}
+
+/// Merges `data` into `sector` at `offset` and tells how the flash has to be updated.
+fn merge(sector: &mut [u8], offset: usize, data: &[u8]) -> SectorUpdate {
+    let current = &mut sector[offset..offset + data.len()];
+    if current == data {
+        return SectorUpdate::Unchanged;
+    }
+    let update = if current.iter().zip(data).all(|(c, d)| c & d == *d) {
+        SectorUpdate::Program
+    } else {
+        SectorUpdate::Erase
+    };
+    current.copy_from_slice(data);
+    update
+}
+
+fn check_erase(from: u32, to: u32) -> Result<(), FlashError> {
+    if from > to || to > CAPACITY {
+        return Err(FlashError::OutOfBounds);
+    }
+    if from % SECTOR_SIZE != 0 || to % SECTOR_SIZE != 0 {
+        return Err(FlashError::NotAligned);
+    }
+    Ok(())
+}
+
+/// How `Flash::write` treats the bytes that share a sector with the written range.
+pub enum WriteMode<'a> {
+    /// Erases the touched sectors first, so bytes around the range in the first and last sector
+    /// are lost. The default.
+    EraseSectors,
+    /// Reads every touched sector into `scratch`, merges the data and writes the sector back.
+    /// Bytes around the range are kept, and sectors are only erased if bits go from 0 to 1.
+    /// The buffer can be in RAM or SDRAM, cached or not: `write_async()` cleans the D-cache over
+    /// it before the DMA programs it. For `write_async()` it must not be in DTCM, which the DMA cannot reach.
+    ReadModifyWrite(&'a mut [u8; SECTOR_SIZE as usize]),
+}
+
 pub struct FlashBuilder<'a> {
     pub pins: FlashPins<'a>,
     pub qspi: Peri<'a, QUADSPI>,
 }
 
 impl<'a> FlashBuilder<'a> {
-    pub fn build(self) -> Flash<'a, Blocking> {
+    /// Returns `FlashError::Timeout` if the chip does not come out of reset.
+    pub fn build(self) -> Result<Flash<'a, Blocking>, FlashError> {
         let config = self.config();
         let Self { pins, qspi } = self;
 
         let qspi = Qspi::new_blocking_bank1(
             qspi, pins.IO0, pins.IO1, pins.IO2, pins.IO3, pins.SCK, pins.CS, config,
         );
-        let mut result = Flash { qspi };
-        result.reset();
-        result
+        let mut result = Flash {
+            qspi,
+            write_mode: WriteMode::EraseSectors,
+        };
+        result.reset()?;
+        Ok(result)
     }
 
-    pub fn build_async<D, I>(self, dma_ch: Peri<'a, D>, irq: I) -> Flash<'a, Async>
+    /// Returns `FlashError::Timeout` if the chip does not come out of reset.
+    pub fn build_async<D, I>(
+        self,
+        dma_ch: Peri<'a, D>,
+        irq: I,
+    ) -> Result<Flash<'a, Async>, FlashError>
     where
         D: QuadDma<QUADSPI>,
         I: Binding<D::Interrupt, dma::InterruptHandler<D>>
@@ -96,9 +195,12 @@ impl<'a> FlashBuilder<'a> {
         let qspi = Qspi::new_bank1(
             qspi, pins.IO0, pins.IO1, pins.IO2, pins.IO3, pins.SCK, pins.CS, dma_ch, irq, config,
         );
-        let mut result = Flash { qspi };
-        result.reset();
-        result
+        let mut result = Flash {
+            qspi,
+            write_mode: WriteMode::EraseSectors,
+        };
+        result.reset()?;
+        Ok(result)
     }
 
     fn config(&self) -> hal::qspi::Config {
@@ -115,12 +217,24 @@ impl<'a> FlashBuilder<'a> {
 
 pub struct Flash<'a, MODE: Mode> {
     qspi: Qspi<'a, QUADSPI, MODE>,
+    write_mode: WriteMode<'a>,
 }
 
-impl<MODE: Mode> Flash<'_, MODE> {
-    pub fn read(&mut self, address: u32, buffer: &mut [u8]) {
-        assert!(address + buffer.len() as u32 <= MAX_ADDRESS);
+impl<'a, MODE: Mode> Flash<'a, MODE> {
+    /// Selects how `write()` and `write_async()` treat the rest of the touched sectors.
+    pub fn set_write_mode(&mut self, write_mode: WriteMode<'a>) {
+        self.write_mode = write_mode;
+    }
 
+    pub fn read(&mut self, address: u32, buffer: &mut [u8]) -> Result<(), FlashError> {
+        check_range(address, buffer.len())?;
+        if !buffer.is_empty() {
+            self.read_unchecked(address, buffer);
+        }
+        Ok(())
+    }
+
+    fn read_unchecked(&mut self, address: u32, buffer: &mut [u8]) {
         let transaction = TransferConfig {
             iwidth: QspiWidth::SING,
             awidth: QspiWidth::QUAD,
@@ -146,11 +260,76 @@ impl<MODE: Mode> Flash<'_, MODE> {
         buffer
     }
 
-    pub fn write(&mut self, mut address: u32, data: &[u8]) {
-        assert!(address <= MAX_ADDRESS);
-        assert!(!data.is_empty());
-        self.erase(address, data.len() as u32);
+    /// Writes `data` according to the `WriteMode` and reads it back.
+    pub fn write(&mut self, address: u32, data: &[u8]) -> Result<(), FlashError> {
+        check_range(address, data.len())?;
+        if data.is_empty() {
+            return Ok(());
+        }
+        if let Some(mut scratch) = self.take_scratch() {
+            let (flash, buffer) = scratch.parts();
+            return flash.read_modify_write(address, data, buffer);
+        }
+        self.erase(address, data.len() as u32)?;
+        self.program_pages(address, data)?;
+        self.verify(address, data)
+    }
+
+    /// Takes the scratch buffer of `WriteMode::ReadModifyWrite` out of `write_mode`, so that it can
+    /// be used next to the flash. The returned `Scratch` puts it back when dropped.
+    fn take_scratch(&mut self) -> Option<Scratch<'_, 'a, MODE>> {
+        match core::mem::replace(&mut self.write_mode, WriteMode::EraseSectors) {
+            WriteMode::EraseSectors => None,
+            WriteMode::ReadModifyWrite(buffer) => Some(Scratch {
+                flash: self,
+                buffer: Some(buffer),
+            }),
+        }
+    }
+
+    /// Programs `data` without erasing. Returns `FlashError::NotErased` if any byte in the range
+    /// is not erased, before anything is written.
+    pub fn program(&mut self, address: u32, data: &[u8]) -> Result<(), FlashError> {
+        check_range(address, data.len())?;
+        if data.is_empty() {
+            return Ok(());
+        }
+        self.check_erased(address, data.len())?;
+        self.program_pages(address, data)?;
+        self.verify(address, data)
+    }
+
+    fn read_modify_write(
+        &mut self,
+        mut address: u32,
+        mut data: &[u8],
+        scratch: &mut [u8; SECTOR_SIZE as usize],
+    ) -> Result<(), FlashError> {
+        while !data.is_empty() {
+            let sector = address & !(SECTOR_SIZE - 1);
+            let offset = (address - sector) as usize;
+            let (chunk, rest) = data.split_at(data.len().min(scratch.len() - offset));
+            self.read_unchecked(sector, scratch);
+            match merge(scratch, offset, chunk) {
+                SectorUpdate::Unchanged => {}
+                SectorUpdate::Program => {
+                    self.program_pages(address, chunk)?;
+                    self.verify(address, chunk)?;
+                }
+                SectorUpdate::Erase => {
+                    self.erase(sector, SECTOR_SIZE)?;
+                    self.program_pages(sector, scratch)?;
+                    self.verify(sector, scratch)?;
+                }
+            }
+            address += chunk.len() as u32;
+            data = rest;
+        }
+        Ok(())
+    }
 
+    /// Programs `data` page by page, without erasing first.
+    fn program_pages(&mut self, mut address: u32, data: &[u8]) -> Result<(), FlashError> {
         let mut length = data.len() as u32;
         let mut start_cursor = 0;
 
@@ -160,7 +339,7 @@ impl<MODE: Mode> Flash<'_, MODE> {
             // Calculate number of bytes between address and end of the page.
             let page_remainder = PAGE_SIZE - (address & (PAGE_SIZE - 1));
             let size = page_remainder.min(length) as usize;
-            self.enable_write();
+            self.enable_write_checked()?;
             let transaction = TransferConfig {
                 iwidth: QspiWidth::SING,
                 awidth: QspiWidth::SING,
@@ -172,28 +351,30 @@ impl<MODE: Mode> Flash<'_, MODE> {
 
             self.qspi
                 .blocking_write(&data[start_cursor..start_cursor + size], transaction);
-            self.wait_for_write();
+            self.wait_for_write(PAGE_WRITE_TIMEOUT)?;
             start_cursor += size;
 
             // Stop if this was the last needed page.
             if length <= page_remainder {
-                break;
+                break Ok(());
             }
             length -= page_remainder;
 
             // Jump to the next page.
             address += page_remainder;
-            address %= MAX_ADDRESS;
         }
     }
 
-    pub fn erase(&mut self, mut address: u32, mut length: u32) {
-        assert!(address <= MAX_ADDRESS);
-        assert!(length > 0);
+    /// Erases every sector touched by `address..address + length`.
+    pub fn erase(&mut self, mut address: u32, mut length: u32) -> Result<(), FlashError> {
+        check_range(address, length as usize)?;
+        if length == 0 {
+            return Ok(());
+        }
 
         loop {
             // Erase the sector.
-            self.enable_write();
+            self.enable_write_checked()?;
             let transaction = TransferConfig {
                 iwidth: QspiWidth::SING,
                 awidth: QspiWidth::SING,
@@ -204,20 +385,19 @@ impl<MODE: Mode> Flash<'_, MODE> {
             };
 
             self.qspi.blocking_command(transaction);
-            self.wait_for_write();
+            self.wait_for_write(SECTOR_ERASE_TIMEOUT)?;
 
             // Calculate number of bytes between address and end of the sector.
             let sector_remainder = SECTOR_SIZE - (address & (SECTOR_SIZE - 1));
 
             // Stop if this was the last affected sector.
             if length <= sector_remainder {
-                break;
+                break Ok(());
             }
             length -= sector_remainder;
 
             // Jump to the next sector.
             address += sector_remainder;
-            address %= MAX_ADDRESS;
         }
     }
 
@@ -233,12 +413,54 @@ impl<MODE: Mode> Flash<'_, MODE> {
         self.qspi.blocking_command(transaction);
     }
 
-    fn wait_for_write(&mut self) {
-        loop {
-            if self.read_status() & STATUS_BIT_WIP == 0 {
-                break;
+    /// `enable_write()`, then checks that the chip accepted it and no block is protected.
+    fn enable_write_checked(&mut self) -> Result<(), FlashError> {
+        self.enable_write();
+        let status = self.read_status();
+        let protection = STATUS_BIT_BP0 | STATUS_BIT_BP1 | STATUS_BIT_BP2 | STATUS_BIT_BP3;
+        if status & STATUS_BIT_WEL == 0 || status & protection != 0 {
+            return Err(FlashError::WriteProtected);
+        }
+        Ok(())
+    }
+
+    fn wait_for_write(&mut self, timeout: Duration) -> Result<(), FlashError> {
+        let deadline = Instant::now() + timeout;
+        while self.read_status() & STATUS_BIT_WIP != 0 {
+            if Instant::now() > deadline {
+                return Err(FlashError::Timeout);
             }
         }
+        Ok(())
+    }
+
+    fn check_erased(&mut self, mut address: u32, length: usize) -> Result<(), FlashError> {
+        let mut buffer = [0; PAGE_SIZE as usize];
+        let mut remaining = length;
+        while remaining > 0 {
+            let buffer = &mut buffer[..remaining.min(PAGE_SIZE as usize)];
+            self.read_unchecked(address, buffer);
+            if buffer.iter().any(|&b| b != 0xFF) {
+                return Err(FlashError::NotErased);
+            }
+            address += buffer.len() as u32;
+            remaining -= buffer.len();
+        }
+        Ok(())
+    }
+
+    /// Reads `data` back page by page and compares it.
+    fn verify(&mut self, mut address: u32, data: &[u8]) -> Result<(), FlashError> {
+        let mut buffer = [0; PAGE_SIZE as usize];
+        for chunk in data.chunks(PAGE_SIZE as usize) {
+            let buffer = &mut buffer[..chunk.len()];
+            self.read_unchecked(address, buffer);
+            if buffer != chunk {
+                return Err(FlashError::VerifyFailed);
+            }
+            address += chunk.len() as u32;
+        }
+        Ok(())
     }
 
     fn read_status(&mut self) -> u8 {
@@ -279,7 +501,7 @@ impl<MODE: Mode> Flash<'_, MODE> {
 
     /// Reset status registers into driver's defaults. This makes sure that the
     /// peripheral is configured as expected.
-    fn reset_status_register(&mut self) {
+    fn reset_status_register(&mut self) -> Result<(), FlashError> {
         self.enable_write();
         let value = STATUS_BIT_QE;
         let transaction = TransferConfig {
@@ -291,12 +513,12 @@ impl<MODE: Mode> Flash<'_, MODE> {
             dummy: DummyCycles::_0,
         };
         self.qspi.blocking_write(&[value], transaction);
-        self.wait_for_write();
+        self.wait_for_write(STATUS_WRITE_TIMEOUT)
     }
 
     /// Reset read registers into driver's defaults. This makes sure that the
     /// peripheral is configured as expected.
-    fn reset_read_register(&mut self) {
+    fn reset_read_register(&mut self) -> Result<(), FlashError> {
         let value = READ_PARAMS_BIT_ODS2
             | READ_PARAMS_BIT_ODS1
             | READ_PARAMS_BIT_ODS0
@@ -310,20 +532,26 @@ impl<MODE: Mode> Flash<'_, MODE> {
             dummy: DummyCycles::_0,
         };
         self.qspi.blocking_write(&[value], transaction);
-        self.wait_for_write();
+        self.wait_for_write(STATUS_WRITE_TIMEOUT)
     }
 
-    fn reset(&mut self) {
+    fn reset(&mut self) -> Result<(), FlashError> {
         self.reset_memory();
-        self.reset_status_register();
-        self.reset_read_register();
+        self.reset_status_register()?;
+        self.reset_read_register()
     }
 }
 
 impl Flash<'_, Async> {
-    pub async fn read_async(&mut self, address: u32, buffer: &mut [u8]) {
-        assert!(address + buffer.len() as u32 <= MAX_ADDRESS);
+    pub async fn read_async(&mut self, address: u32, buffer: &mut [u8]) -> Result<(), FlashError> {
+        check_range(address, buffer.len())?;
+        if !buffer.is_empty() {
+            self.read_unchecked_async(address, buffer).await;
+        }
+        Ok(())
+    }
 
+    async fn read_unchecked_async(&mut self, address: u32, buffer: &mut [u8]) {
         let transaction = TransferConfig {
             iwidth: QspiWidth::SING,
             awidth: QspiWidth::QUAD,
@@ -335,12 +563,73 @@ impl Flash<'_, Async> {
         self.qspi.read_dma(buffer, transaction).await;
     }
 
-    pub async fn write_async(&mut self, mut address: u32, data: &[u8]) {
-        assert!(address <= MAX_ADDRESS);
-        assert!(!data.is_empty());
-        self.erase_async(address, data.len() as u32).await;
+    /// Same as `write()`.
+    pub async fn write_async(&mut self, address: u32, data: &[u8]) -> Result<(), FlashError> {
+        check_range(address, data.len())?;
+        if data.is_empty() {
+            return Ok(());
+        }
+        if let Some(mut scratch) = self.take_scratch() {
+            let (flash, buffer) = scratch.parts();
+            return flash.read_modify_write_async(address, data, buffer).await;
+        }
+        self.erase_async(address, data.len() as u32).await?;
+        self.program_pages_async(address, data).await?;
+        self.verify_async(address, data).await
+    }
+
+    /// Same as `program()`.
+    pub async fn program_async(&mut self, address: u32, data: &[u8]) -> Result<(), FlashError> {
+        check_range(address, data.len())?;
+        if data.is_empty() {
+            return Ok(());
+        }
+        self.check_erased_async(address, data.len()).await?;
+        self.program_pages_async(address, data).await?;
+        self.verify_async(address, data).await
+    }
+
+    /// The sectors are read through a buffer on the stack, so the scratch buffer needs no cache
+    /// invalidation. The merged sector may still sit dirty in the D-cache, `program_pages_async` cleans it.
+    async fn read_modify_write_async(
+        &mut self,
+        mut address: u32,
+        mut data: &[u8],
+        scratch: &mut [u8; SECTOR_SIZE as usize],
+    ) -> Result<(), FlashError> {
+        while !data.is_empty() {
+            let sector = address & !(SECTOR_SIZE - 1);
+            let offset = (address - sector) as usize;
+            let (chunk, rest) = data.split_at(data.len().min(scratch.len() - offset));
+            self.read_pages_async(sector, scratch).await;
+            match merge(scratch, offset, chunk) {
+                SectorUpdate::Unchanged => {}
+                SectorUpdate::Program => {
+                    self.program_pages_async(address, chunk).await?;
+                    self.verify_async(address, chunk).await?;
+                }
+                SectorUpdate::Erase => {
+                    self.erase_async(sector, SECTOR_SIZE).await?;
+                    self.program_pages_async(sector, scratch).await?;
+                    self.verify_async(sector, scratch).await?;
+                }
+            }
+            address += chunk.len() as u32;
+            data = rest;
+        }
+        Ok(())
+    }
+
+    /// Programs `data` page by page, without erasing first.
+    async fn program_pages_async(
+        &mut self,
+        mut address: u32,
+        data: &[u8],
+    ) -> Result<(), FlashError> {
         let mut length = data.len() as u32;
         let mut start_cursor = 0;
+        // The DMA reads memory, not the D-cache.
+        clean_dcache(data);
 
         //WRITE_CMD(or PPQ) allows to write up to 256 bytes, which is as much as PAGE_SIZE.
         //Let's divide the data into chunks of page size to write to flash
@@ -348,7 +637,7 @@ impl Flash<'_, Async> {
             // Calculate number of bytes between address and end of the page.
             let page_remainder = PAGE_SIZE - (address & (PAGE_SIZE - 1));
             let size = page_remainder.min(length) as usize;
-            self.enable_write();
+            self.enable_write_checked()?;
             let transaction = TransferConfig {
                 iwidth: QspiWidth::SING,
                 awidth: QspiWidth::SING,
@@ -361,28 +650,34 @@ impl Flash<'_, Async> {
             self.qspi
                 .write_dma(&data[start_cursor..start_cursor + size], transaction)
                 .await;
-            self.wait_for_write_async(PAGE_WRITE_TIMEOUT).await;
+            self.wait_for_write_async(PAGE_WRITE_TIMEOUT).await?;
             start_cursor += size;
 
             // Stop if this was the last needed page.
             if length <= page_remainder {
-                break;
+                break Ok(());
             }
             length -= page_remainder;
 
             // Jump to the next page.
             address += page_remainder;
-            address %= MAX_ADDRESS;
         }
     }
 
-    pub async fn erase_async(&mut self, mut address: u32, mut length: u32) {
-        assert!(address <= MAX_ADDRESS);
-        assert!(length > 0);
+    /// Same as `erase()`.
+    pub async fn erase_async(
+        &mut self,
+        mut address: u32,
+        mut length: u32,
+    ) -> Result<(), FlashError> {
+        check_range(address, length as usize)?;
+        if length == 0 {
+            return Ok(());
+        }
 
         loop {
             // Erase the sector.
-            self.enable_write();
+            self.enable_write_checked()?;
             let transaction = TransferConfig {
                 iwidth: QspiWidth::SING,
                 awidth: QspiWidth::SING,
@@ -393,24 +688,23 @@ impl Flash<'_, Async> {
             };
             self.qspi.blocking_command(transaction);
 
-            self.wait_for_write_async(SECTOR_ERASE_TIMEOUT).await;
+            self.wait_for_write_async(SECTOR_ERASE_TIMEOUT).await?;
 
             // Calculate number of bytes between address and end of the sector.
             let sector_remainder = SECTOR_SIZE - (address & (SECTOR_SIZE - 1));
 
             // Stop if this was the last affected sector.
             if length <= sector_remainder {
-                break;
+                break Ok(());
             }
             length -= sector_remainder;
 
             // Jump to the next sector.
             address += sector_remainder;
-            address %= MAX_ADDRESS;
         }
     }
 
-    async fn wait_for_write_async(&mut self, timeout: Duration) {
+    async fn wait_for_write_async(&mut self, timeout: Duration) -> Result<(), FlashError> {
         let transaction = TransferConfig {
             iwidth: QspiWidth::SING,
             awidth: QspiWidth::NONE,
@@ -431,6 +725,161 @@ impl Flash<'_, Async> {
             )
             .with_timeout(timeout)
             .await
-            .expect("Flash Timed out waiting for status match")
+            .map_err(|_| FlashError::Timeout)
+    }
+
+    /// Same as `check_erased()`.
+    async fn check_erased_async(
+        &mut self,
+        mut address: u32,
+        length: usize,
+    ) -> Result<(), FlashError> {
+        let mut buffer = [0; PAGE_SIZE as usize];
+        let mut remaining = length;
+        while remaining > 0 {
+            let buffer = &mut buffer[..remaining.min(PAGE_SIZE as usize)];
+            self.read_unchecked_async(address, buffer).await;
+            if buffer.iter().any(|&b| b != 0xFF) {
+                return Err(FlashError::NotErased);
+            }
+            address += buffer.len() as u32;
+            remaining -= buffer.len();
+        }
+        Ok(())
+    }
+
+    /// Reads into `data` page by page through a buffer on the stack, like `verify_async`, so `data`
+    /// may be anywhere and cached.
+    async fn read_pages_async(&mut self, mut address: u32, data: &mut [u8]) {
+        let mut buffer = [0; PAGE_SIZE as usize];
+        for chunk in data.chunks_mut(PAGE_SIZE as usize) {
+            let buffer = &mut buffer[..chunk.len()];
+            self.read_unchecked_async(address, buffer).await;
+            chunk.copy_from_slice(buffer);
+            address += chunk.len() as u32;
+        }
+    }
+
+    async fn verify_async(&mut self, mut address: u32, data: &[u8]) -> Result<(), FlashError> {
+        let mut buffer = [0; PAGE_SIZE as usize];
+        for chunk in data.chunks(PAGE_SIZE as usize) {
+            let buffer = &mut buffer[..chunk.len()];
+            self.read_unchecked_async(address, buffer).await;
+            if buffer != chunk {
+                return Err(FlashError::VerifyFailed);
+            }
+            address += chunk.len() as u32;
+        }
+        Ok(())
+    }
+}
+
+/// The scratch buffer of `WriteMode::ReadModifyWrite` while `write()` or `write_async()` uses it,
+/// see `Flash::take_scratch()`. Also restores the write mode if `write_async()` is cancelled.
+struct Scratch<'f, 'a, MODE: Mode> {
+    flash: &'f mut Flash<'a, MODE>,
+    // only `None` in `drop()`
+    buffer: Option<&'a mut [u8; SECTOR_SIZE as usize]>,
+}
+
+impl<'a, MODE: Mode> Scratch<'_, 'a, MODE> {
+    fn parts(&mut self) -> (&mut Flash<'a, MODE>, &mut [u8; SECTOR_SIZE as usize]) {
+        match &mut self.buffer {
+            Some(buffer) => (&mut *self.flash, &mut **buffer),
+            None => defmt::unreachable!(),
+        }
     }
 }
+
+impl<MODE: Mode> Drop for Scratch<'_, '_, MODE> {
+    fn drop(&mut self) {
+        if let Some(buffer) = self.buffer.take() {
+            self.flash.write_mode = WriteMode::ReadModifyWrite(buffer);
+        }
+    }
+}
+
+impl<MODE: Mode> ErrorType for Flash<'_, MODE> {
+    type Error = FlashError;
+}
+
+impl<MODE: Mode> ReadNorFlash for Flash<'_, MODE> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), FlashError> {
+        check_range(offset, bytes.len())?;
+        if !bytes.is_empty() {
+            self.read_unchecked(offset, bytes);
+        }
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        CAPACITY as usize
+    }
+}
+
+impl<MODE: Mode> NorFlash for Flash<'_, MODE> {
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = SECTOR_SIZE as usize;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), FlashError> {
+        check_erase(from, to)?;
+        if from < to {
+            Flash::erase(self, from, to - from)?;
+        }
+        Ok(())
+    }
+
+    /// Only programs, the range has to be erased before.
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), FlashError> {
+        check_range(offset, bytes.len())?;
+        if !bytes.is_empty() {
+            self.program_pages(offset, bytes)?;
+        }
+        Ok(())
+    }
+}
+
+/// Page program only clears bits, so a byte can be programmed again as long as bits only go from 1 to 0.
+impl<MODE: Mode> MultiwriteNorFlash for Flash<'_, MODE> {}
+
+impl async_nor_flash::ReadNorFlash for Flash<'_, Async> {
+    const READ_SIZE: usize = 1;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), FlashError> {
+        check_range(offset, bytes.len())?;
+        if !bytes.is_empty() {
+            self.read_unchecked_async(offset, bytes).await;
+        }
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        CAPACITY as usize
+    }
+}
+
+impl async_nor_flash::NorFlash for Flash<'_, Async> {
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = SECTOR_SIZE as usize;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), FlashError> {
+        check_erase(from, to)?;
+        if from < to {
+            self.erase_async(from, to - from).await?;
+        }
+        Ok(())
+    }
+
+    /// Only programs, the range has to be erased before.
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), FlashError> {
+        check_range(offset, bytes.len())?;
+        if !bytes.is_empty() {
+            self.program_pages_async(offset, bytes).await?;
+        }
+        Ok(())
+    }
+}
+
+impl async_nor_flash::MultiwriteNorFlash for Flash<'_, Async> {}
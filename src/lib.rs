@@ -3,25 +3,56 @@
 // use same configuration concept as https://github.com/zlosynth/daisy
 #[cfg(all(
     feature = "seed_1_2",
-    any(feature = "seed_1_1", feature = "seed", feature = "patch_sm")
+    any(
+        feature = "seed_1_1",
+        feature = "seed",
+        feature = "patch_sm",
+        feature = "auto"
+    )
 ))]
 compile_error!("only a single target board must be selected");
 
 #[cfg(all(
     feature = "seed_1_1",
-    any(feature = "seed_1_2", feature = "seed", feature = "patch_sm")
+    any(
+        feature = "seed_1_2",
+        feature = "seed",
+        feature = "patch_sm",
+        feature = "auto"
+    )
 ))]
 compile_error!("only a single target board must be selected");
 
 #[cfg(all(
     feature = "seed",
-    any(feature = "seed_1_2", feature = "seed_1_1", feature = "patch_sm")
+    any(
+        feature = "seed_1_2",
+        feature = "seed_1_1",
+        feature = "patch_sm",
+        feature = "auto"
+    )
 ))]
 compile_error!("only a single target board must be selected");
 
 #[cfg(all(
     feature = "patch_sm",
-    any(feature = "seed_1_2", feature = "seed_1_1", feature = "seed")
+    any(
+        feature = "seed_1_2",
+        feature = "seed_1_1",
+        feature = "seed",
+        feature = "auto"
+    )
+))]
+compile_error!("only a single target board must be selected");
+
+#[cfg(all(
+    feature = "auto",
+    any(
+        feature = "seed_1_2",
+        feature = "seed_1_1",
+        feature = "seed",
+        feature = "patch_sm"
+    )
 ))]
 compile_error!("only a single target board must be selected");
 
@@ -29,15 +60,17 @@ compile_error!("only a single target board must be selected");
     feature = "seed_1_2",
     feature = "seed_1_1",
     feature = "seed",
-    feature = "patch_sm"
+    feature = "patch_sm",
+    feature = "auto"
 )))]
 compile_error!(
-    "target board must be selected using a feature: \"seed_1_2\" | \"seed_1_1\" | \"seed\" | \"patch_sm\""
+    "target board must be selected using a feature: \"seed_1_2\" | \"seed_1_1\" | \"seed\" | \"patch_sm\" | \"auto\""
 );
 
 pub mod audio;
 pub mod board;
 pub mod codec;
+mod dcache;
 pub mod flash;
 pub mod led;
 pub mod pins;
@@ -45,9 +78,18 @@ pub mod sdram;
 pub mod usb;
 
 pub use board::DaisyBoard;
-pub use codec::{Codec, Pins as CodecPins};
+pub use codec::{AudioCodec, Codec, CodecError, Pins as CodecPins, PowerState};
 pub use embassy_stm32 as hal;
 
+/// Clock configuration for the 48kHz family: 8k, 16k, 24k, 32k, 48k, 96k and 192kHz.
+/// PLL3 feeds SAI1 and SAI2 with 49.152MHz, which divides exactly into all of those rates
+/// at 256 x fs, and into those up to 96kHz at 512 x fs.
+///
+/// The 44.1kHz family (22.05k, 44.1k, 88.2k and 176.4kHz) needs the clock tree from `rcc_for(fs)`;
+/// with this one, `AudioConfig::validate` rejects those rates as unreachable.
+///
+/// None of the built-in codecs run at 176.4kHz or 192kHz (see `AudioCodec::CAPABILITIES`),
+/// those rates are only for codec drivers passed to `prepare_interface_with_codec()`.
 pub fn default_rcc() -> hal::Config {
     let mut config = hal::Config::default();
     use hal::rcc::*;
@@ -79,6 +121,7 @@ pub fn default_rcc() -> hal::Config {
     config.rcc.sys = Sysclk::PLL1_P; // 480MHz
     config.rcc.mux.fmcsel = hal::pac::rcc::vals::Fmcsel::PLL2_R; //  166Mhz
     config.rcc.mux.sai1sel = hal::pac::rcc::vals::Saisel::PLL3_P; // 49.2MHz
+    config.rcc.mux.sai23sel = hal::pac::rcc::vals::Saisel::PLL3_P; // 49.2MHz, same as SAI1 for a secondary codec
     config.rcc.mux.usbsel = hal::pac::rcc::vals::Usbsel::PLL1_Q; // 48MHz
     config.rcc.mux.adcsel = hal::pac::rcc::vals::Adcsel::PLL3_R; // 98.33Mhz 
     config.rcc.ahb_pre = AHBPrescaler::DIV2; // 240 MHz
@@ -95,6 +138,26 @@ pub fn default_rcc() -> hal::Config {
     config
 }
 
+/// Same as `default_rcc`, but with the SAI kernel clock (PLL3) chosen for the family of `fs`.
+///
+/// For 44.1kHz-family rates PLL3 runs at 45.1556MHz, the closest integer PLL setting to the ideal
+/// 45.1584MHz (-63ppm). Use `audio::AudioConfig::accuracy` to get the exact figures for any rate.
+pub fn rcc_for(fs: audio::Fs) -> hal::Config {
+    let mut config = default_rcc();
+    if fs.is_44k1_family() {
+        use hal::rcc::*;
+        config.rcc.pll3 = Some(Pll {
+            source: PllSource::HSE,
+            prediv: PllPreDiv::DIV5,  // 3.2Mhz
+            mul: PllMul::MUL127,      // 406.4Mhz
+            divp: Some(PllDiv::DIV9), // 45.1556 Mhz for SAI
+            divq: None,
+            divr: Some(PllDiv::DIV5), // 81.28 Mhz
+        });
+    }
+    config
+}
+
 #[cfg(feature = "seed")]
 #[macro_export]
 macro_rules! codec_pins {
@@ -141,6 +204,27 @@ macro_rules! codec_pins {
     };
 }
 
+#[cfg(feature = "auto")]
+#[macro_export]
+macro_rules! codec_pins {
+    ($p:ident) => {
+        daisy_embassy::CodecPins {
+            // RESET of the AK4556 on Seed, SDA of the WM8731 on Seed 1.1
+            SCL: $p.PH4,
+            SDA: $p.PB11,
+
+            MCLK_A: $p.PE2,
+            SCK_A: $p.PE5,
+            FS_A: $p.PE4,
+            SD_A: $p.PE6,
+            SD_B: $p.PE3,
+
+            DETECT_1_1: $p.PD3,
+            DETECT_1_2: $p.PD4,
+        }
+    };
+}
+
 #[cfg(feature = "patch_sm")]
 #[macro_export]
 macro_rules! codec_pins {
@@ -158,7 +242,12 @@ macro_rules! codec_pins {
     };
 }
 
-#[cfg(any(feature = "seed", feature = "seed_1_1", feature = "seed_1_2"))]
+#[cfg(any(
+    feature = "seed",
+    feature = "seed_1_1",
+    feature = "seed_1_2",
+    feature = "auto"
+))]
 #[macro_export]
 macro_rules! daisy_pins {
     ($p:ident) => {
@@ -237,20 +326,64 @@ macro_rules! daisy_pins {
     };
 }
 
+// The codecs configured over I2C also take DMA1 streams 4 and 5, and their interrupts.
+// `audio_peripherals!(p)` binds those in the calling crate; `audio_peripherals!(p, irqs)` takes
+// your own `Irqs`, see `codec::CodecI2cIrqs`.
+#[cfg(any(feature = "seed_1_1", feature = "patch_sm", feature = "auto"))]
+#[macro_export]
+macro_rules! audio_peripherals {
+    ($p:ident) => {{
+        daisy_embassy::hal::bind_interrupts!(struct CodecI2cIrqs {
+            DMA1_STREAM4 => daisy_embassy::hal::dma::InterruptHandler<daisy_embassy::hal::peripherals::DMA1_CH4>;
+            DMA1_STREAM5 => daisy_embassy::hal::dma::InterruptHandler<daisy_embassy::hal::peripherals::DMA1_CH5>;
+            I2C2_EV => daisy_embassy::hal::i2c::EventInterruptHandler<daisy_embassy::hal::peripherals::I2C2>;
+            I2C2_ER => daisy_embassy::hal::i2c::ErrorInterruptHandler<daisy_embassy::hal::peripherals::I2C2>;
+        });
+        daisy_embassy::audio_peripherals!($p, CodecI2cIrqs)
+    }};
+    ($p:ident, $irqs:expr) => {
+        daisy_embassy::audio::AudioPeripherals {
+            codec_pins: daisy_embassy::codec_pins!($p),
+            sai1: $p.SAI1,
+            i2c2: $p.I2C2,
+            dma1_ch0: $p.DMA1_CH0,
+            dma1_ch1: $p.DMA1_CH1,
+            dma1_ch4: $p.DMA1_CH4,
+            dma1_ch5: $p.DMA1_CH5,
+            i2c_irqs: daisy_embassy::codec::CodecI2cIrqs::new($irqs),
+        }
+    };
+}
+
+#[cfg(any(feature = "seed", feature = "seed_1_2"))]
+#[macro_export]
+macro_rules! audio_peripherals {
+    ($p:ident) => {
+        daisy_embassy::audio::AudioPeripherals {
+            codec_pins: daisy_embassy::codec_pins!($p),
+            sai1: $p.SAI1,
+            i2c2: $p.I2C2,
+            dma1_ch0: $p.DMA1_CH0,
+            dma1_ch1: $p.DMA1_CH1,
+        }
+    };
+}
+
 #[macro_export]
 macro_rules! new_daisy_board {
     ($p:ident) => {
+        daisy_embassy::new_daisy_board!(@with $p, daisy_embassy::audio_peripherals!($p))
+    };
+    // for `seed_1_1`, `patch_sm` and `auto`, when your crate binds the codec I2C interrupts
+    ($p:ident, $irqs:expr) => {
+        daisy_embassy::new_daisy_board!(@with $p, daisy_embassy::audio_peripherals!($p, $irqs))
+    };
+    (@with $p:ident, $audio_peripherals:expr) => {
         daisy_embassy::board::DaisyBoard {
             pins: daisy_embassy::daisy_pins!($p),
             user_led: daisy_embassy::led::UserLed::new($p.PC7),
 
-            audio_peripherals: daisy_embassy::audio::AudioPeripherals {
-                codec_pins: daisy_embassy::codec_pins!($p),
-                sai1: $p.SAI1,
-                i2c2: $p.I2C2,
-                dma1_ch0: $p.DMA1_CH0,
-                dma1_ch1: $p.DMA1_CH1,
-            },
+            audio_peripherals: $audio_peripherals,
 
             flash: daisy_embassy::flash::FlashBuilder {
                 pins: daisy_embassy::pins::FlashPins {
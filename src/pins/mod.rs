@@ -1,7 +1,17 @@
-#[cfg(any(feature = "seed", feature = "seed_1_1", feature = "seed_1_2"))]
+#[cfg(any(
+    feature = "seed",
+    feature = "seed_1_1",
+    feature = "seed_1_2",
+    feature = "auto"
+))]
 mod pins_seed;
 
-#[cfg(any(feature = "seed", feature = "seed_1_1", feature = "seed_1_2"))]
+#[cfg(any(
+    feature = "seed",
+    feature = "seed_1_1",
+    feature = "seed_1_2",
+    feature = "auto"
+))]
 pub use pins_seed::*;
 
 #[cfg(feature = "patch_sm")]